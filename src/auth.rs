@@ -0,0 +1,66 @@
+// Everyone who connects can watch the garden, only holders of the admin
+// token can change it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Role {
+    Spectator,
+    Admin,
+}
+
+impl Role {
+    // With no admin token configured nobody can take control
+    pub fn authorize(admin_token: Option<&str>, offered_token: Option<&str>) -> Role {
+        match (admin_token, offered_token) {
+            (Some(expected), Some(offered)) if tokens_match(expected, offered) => Role::Admin,
+            _ => Role::Spectator,
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        *self == Role::Admin
+    }
+}
+
+pub fn token_from_query(query: Option<&str>) -> Option<&str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("token="))
+        .next()
+}
+
+// Compare every byte so the time taken doesn't leak how much of the token matched
+fn tokens_match(expected: &str, offered: &str) -> bool {
+    if expected.len() != offered.len() {
+        return false;
+    }
+    expected
+        .bytes()
+        .zip(offered.bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        assert_eq!(Role::authorize(Some("secret"), Some("secret")), Role::Admin);
+        assert_eq!(
+            Role::authorize(Some("secret"), Some("secreT")),
+            Role::Spectator
+        );
+        assert_eq!(Role::authorize(Some("secret"), None), Role::Spectator);
+        assert_eq!(Role::authorize(None, Some("")), Role::Spectator);
+    }
+
+    #[test]
+    fn test_token_from_query() {
+        assert_eq!(
+            token_from_query(Some("encoding=binary&token=abc")),
+            Some("abc")
+        );
+        assert_eq!(token_from_query(Some("encoding=binary")), None);
+        assert_eq!(token_from_query(None), None);
+    }
+}
@@ -0,0 +1,33 @@
+use std::env;
+use std::process;
+
+use garden::headless::{self, SimError, SimOptions};
+use garden::sweep::{self, SweepOptions};
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let is_sweep = args.peek().map(String::as_str) == Some("sweep");
+    let result = if is_sweep {
+        args.next();
+        SweepOptions::from_args(args).and_then(|options| sweep::run(&options))
+    } else {
+        SimOptions::from_args(args).and_then(|options| headless::run(&options))
+    };
+
+    match result {
+        Ok(()) => (),
+        Err(SimError::HelpRequested) if is_sweep => println!("{}", sweep::USAGE),
+        Err(SimError::HelpRequested) => println!("{}", headless::USAGE),
+        Err(e @ SimError::UnknownFlag(_))
+        | Err(e @ SimError::MissingValue(_))
+        | Err(e @ SimError::InvalidValue { .. })
+        | Err(e @ SimError::TooManyCombinations) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::scheduler::{self, Speed};
+use crate::simulation::{Stop, Until};
+use crate::world::events::WorldEvent;
+use crate::world::population::PopulationStats;
+use crate::world::Position;
+
+pub const MIN_TICK_RATE_MS: u64 = 10;
+pub const MAX_TICK_RATE_MS: u64 = 10_000;
+pub const MAX_STEPS: u32 = 1_000;
+
+// Sent by clients as JSON text frames, e.g. `{"command": "step", "n": 5}`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Pause,
+    Resume,
+    Step { n: u32 },
+    SetTickRate { ms: u64 },
+    SetSpeed { speed: Speed },
+    // Resumes the world, pausing it again once `until` is met
+    RunUntil { until: Until },
+    // Shows this session the world as it was at `tick` instead of the live world
+    ViewTick { tick: u64 },
+    ViewLive,
+    // Rewinds the live world to `tick`, dropping everything after it
+    Fork { tick: u64 },
+    // Drops food on an empty cell at the next tick, open to every visitor
+    PlaceFood { position: Position },
+    // Sends the session the live world's population stats after every tick
+    SubscribeStats,
+    UnsubscribeStats,
+    // Sends the session the live world's events after every tick
+    SubscribeEvents,
+    UnsubscribeEvents,
+}
+
+// Every command gets exactly one reply so clients can match them up in order
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum Reply {
+    Ack { command: Command },
+    Error { message: String },
+}
+
+// Sent unprompted, e.g. to control sessions when a run started by `step` or
+// `run_until` finishes
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "notification", rename_all = "snake_case")]
+pub enum Notification {
+    Stopped(Stop),
+    // To sessions that sent `subscribe_stats`
+    Stats(PopulationStats),
+    // To sessions that sent `subscribe_events`
+    Events { tick: u64, events: Vec<WorldEvent> },
+    // The session read too slowly and missed these ticks' events
+    EventsMissed { ticks: u64 },
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Malformed(serde_json::Error),
+    OutOfBounds {
+        field: &'static str,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Malformed(e) => write!(f, "Malformed command: {}", e),
+            CommandError::OutOfBounds {
+                field,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} must be between {} and {} (got {})",
+                field, min, max, value
+            ),
+        }
+    }
+}
+
+impl Command {
+    pub fn parse(msg: &str) -> Result<Command, CommandError> {
+        let command: Command = serde_json::from_str(msg).map_err(CommandError::Malformed)?;
+        command.validate()?;
+        Ok(command)
+    }
+
+    // Spectators may watch but not steer the simulation
+    pub fn requires_admin(&self) -> bool {
+        match self {
+            Command::Pause
+            | Command::Resume
+            | Command::Step { .. }
+            | Command::SetTickRate { .. }
+            | Command::SetSpeed { .. }
+            | Command::RunUntil { .. }
+            | Command::ViewTick { .. }
+            | Command::ViewLive
+            | Command::Fork { .. } => true,
+            Command::PlaceFood { .. }
+            | Command::SubscribeStats
+            | Command::UnsubscribeStats
+            | Command::SubscribeEvents
+            | Command::UnsubscribeEvents => false,
+        }
+    }
+
+    fn validate(&self) -> Result<(), CommandError> {
+        match self {
+            Command::Step { n } => check_bounds("n", *n as f64, 1.0, MAX_STEPS as f64),
+            Command::SetTickRate { ms } => check_bounds(
+                "ms",
+                *ms as f64,
+                MIN_TICK_RATE_MS as f64,
+                MAX_TICK_RATE_MS as f64,
+            ),
+            Command::SetSpeed {
+                speed: Speed::Multiplier(multiplier),
+            } => check_bounds(
+                "multiplier",
+                *multiplier,
+                scheduler::MIN_SPEED,
+                scheduler::MAX_SPEED,
+            ),
+            Command::Pause
+            | Command::Resume
+            | Command::RunUntil { .. }
+            | Command::ViewTick { .. }
+            | Command::ViewLive
+            | Command::Fork { .. }
+            | Command::SetSpeed {
+                speed: Speed::AsFastAsPossible,
+            } => Ok(()),
+            // Whether the cell exists and is free depends on the world
+            Command::PlaceFood { .. } => Ok(()),
+            Command::SubscribeStats
+            | Command::UnsubscribeStats
+            | Command::SubscribeEvents
+            | Command::UnsubscribeEvents => Ok(()),
+        }
+    }
+}
+
+impl Reply {
+    pub fn to_message(&self) -> String {
+        // Reply only holds strings and integers so this cannot fail
+        serde_json::to_string(self).expect("unable to serialize reply")
+    }
+}
+
+impl Notification {
+    pub fn to_message(&self) -> String {
+        serde_json::to_string(self).expect("unable to serialize notification")
+    }
+}
+
+fn check_bounds(field: &'static str, value: f64, min: f64, max: f64) -> Result<(), CommandError> {
+    // Written so NaN is out of bounds too
+    if !(value >= min && value <= max) {
+        return Err(CommandError::OutOfBounds {
+            field,
+            value,
+            min,
+            max,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            Command::parse(r#"{"command": "pause"}"#).unwrap(),
+            Command::Pause
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "step", "n": 5}"#).unwrap(),
+            Command::Step { n: 5 }
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "set_tick_rate", "ms": 150}"#).unwrap(),
+            Command::SetTickRate { ms: 150 }
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "set_speed", "speed": {"multiplier": 0.5}}"#).unwrap(),
+            Command::SetSpeed {
+                speed: Speed::Multiplier(0.5)
+            }
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "set_speed", "speed": "as_fast_as_possible"}"#).unwrap(),
+            Command::SetSpeed {
+                speed: Speed::AsFastAsPossible
+            }
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "run_until", "until": {"eaters_above": 40}}"#).unwrap(),
+            Command::RunUntil {
+                until: Until::EatersAbove(40)
+            }
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "run_until", "until": "no_eaters"}"#).unwrap(),
+            Command::RunUntil {
+                until: Until::NoEaters
+            }
+        );
+        let place_food = r#"{"command": "place_food", "position": {"x": 3, "y": 4}}"#;
+        assert_eq!(
+            Command::parse(place_food).unwrap(),
+            Command::PlaceFood {
+                position: Position { x: 3, y: 4 }
+            }
+        );
+        assert!(!Command::parse(place_food).unwrap().requires_admin());
+        let subscribe = Command::parse(r#"{"command": "subscribe_stats"}"#).unwrap();
+        assert_eq!(subscribe, Command::SubscribeStats);
+        assert!(!subscribe.requires_admin());
+    }
+
+    #[test]
+    fn test_notification_format() {
+        let stopped = Notification::Stopped(Stop {
+            id: 3,
+            tick: 120,
+            until: Until::Tick(120),
+        });
+        assert_eq!(
+            stopped.to_message(),
+            r#"{"notification":"stopped","tick":120,"until":{"tick":120}}"#
+        );
+
+        let stats = Notification::Stats(PopulationStats {
+            tick: 4,
+            ..PopulationStats::default()
+        });
+        assert!(stats
+            .to_message()
+            .starts_with(r#"{"notification":"stats","tick":4,"counts":{}"#));
+
+        let events = Notification::Events {
+            tick: 9,
+            events: vec![WorldEvent::Ate { eater: 1, food: 2 }],
+        };
+        assert_eq!(
+            events.to_message(),
+            r#"{"notification":"events","tick":9,"events":[{"event":"ate","eater":1,"food":2}]}"#
+        );
+    }
+
+    #[test]
+    fn test_reject_invalid_commands() {
+        assert!(matches!(
+            Command::parse("tickrate-150"),
+            Err(CommandError::Malformed(_))
+        ));
+        assert!(matches!(
+            Command::parse(r#"{"command": "set_tick_rate", "ms": 0}"#),
+            Err(CommandError::OutOfBounds { field: "ms", .. })
+        ));
+        assert!(matches!(
+            Command::parse(r#"{"command": "step", "n": 0}"#),
+            Err(CommandError::OutOfBounds { field: "n", .. })
+        ));
+        assert!(matches!(
+            Command::parse(r#"{"command": "set_speed", "speed": {"multiplier": 32}}"#),
+            Err(CommandError::OutOfBounds {
+                field: "multiplier",
+                ..
+            })
+        ));
+    }
+}
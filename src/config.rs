@@ -0,0 +1,506 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+use rand_core::SeedableRng;
+use serde::Deserialize;
+
+use crate::history::History;
+use crate::logging::{LogFormat, LogLevels};
+
+pub const USAGE: &str = "Usage: garden [OPTIONS]
+
+Options (each can also be set by the environment variable in brackets, or in
+the JSON config file using the option name with underscores):
+    --config <PATH>          JSON config file [GARDEN_CONFIG]
+    --bind-address <ADDR>    Address to listen on (default 0.0.0.0) [BIND_ADDRESS]
+    --port <PORT>            Port to listen on (default 7878) [PORT]
+    --host-address <HOST>    Host browsers use to reach the server (default localhost) [HOST_ADDRESS]
+    --public-url <URL>       Full URL browsers use, overrides host address and port [PUBLIC_URL]
+    --workers <N>            Request handling threads (default 4) [WORKERS]
+    --simulation-threads <N> Threads used to update the world (default: one per core) [SIMULATION_THREADS]
+    --max-connections <N>    Open websockets allowed at once (default 64) [MAX_CONNECTIONS]
+    --tick-rate-ms <MS>      Milliseconds between world updates (default 100) [TICK_RATE_MS]
+    --history-interval <N>   Ticks between rewind checkpoints (default 50) [HISTORY_INTERVAL]
+    --history-length <N>     Rewind checkpoints kept (default 100) [HISTORY_LENGTH]
+    --max-catch-up-ticks <N> Ticks run back to back to make up for slow ones (default 5) [MAX_CATCH_UP_TICKS]
+    --food-cooldown-ms <MS>  How often each visitor can place food, 0 for no limit (default 1000) [FOOD_COOLDOWN_MS]
+    --seed <N>               Seed for the world's randomizer [SEED]
+    --scenario <PATH>        Scenario to load instead of the default world [SCENARIO]
+    --snapshot <PATH>        Where to save the world on shutdown (default snapshot.json) [SNAPSHOT]
+    --event-log <PATH>       Append the world's events to this file as JSON Lines [EVENT_LOG]
+    --log-format <FORMAT>    text or json (default text) [LOG_FORMAT]
+    --log-level <LEVELS>     e.g. info,garden::registry=debug (default info) [LOG_LEVEL, or RUST_LOG]
+
+The admin token can only be set through ADMIN_TOKEN or the config file.
+Command line flags take precedence over environment variables, which take
+precedence over the config file.";
+
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 7878;
+const DEFAULT_HOST_ADDRESS: &str = "localhost";
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+const DEFAULT_TICK_RATE_MS: u64 = 100;
+const DEFAULT_MAX_CATCH_UP_TICKS: u32 = 5;
+const DEFAULT_HISTORY_INTERVAL: u64 = 50;
+const DEFAULT_HISTORY_LENGTH: usize = 100;
+const DEFAULT_FOOD_COOLDOWN_MS: u64 = 1000;
+const DEFAULT_SNAPSHOT: &str = "snapshot.json";
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bind_address: String,
+    pub port: u16,
+    pub host_address: String,
+    pub public_url: Option<String>,
+    pub workers: usize,
+    // Seeded worlds play out the same whatever this is set to
+    pub simulation_threads: usize,
+    // Websockets each hold a thread for as long as they're open
+    pub max_connections: usize,
+    pub tick_rate_ms: u64,
+    // Beyond this a backlog of ticks is dropped instead of run
+    pub max_catch_up_ticks: u32,
+    // Rewinding can go back history_interval * history_length ticks
+    pub history_interval: u64,
+    pub history_length: usize,
+    // Per websocket session, so one visitor can't bury the garden in food
+    pub food_cooldown_ms: u64,
+    // Without a seed the world runs with the same randomizer it always has
+    pub seed: Option<u64>,
+    pub scenario: Option<PathBuf>,
+    // Written on shutdown in scenario format, so it can be loaded back with --scenario
+    pub snapshot: PathBuf,
+    // Only the default world's events are logged
+    pub event_log: Option<PathBuf>,
+    pub log_format: LogFormat,
+    // Parsed as LogLevels, checked when the config is loaded
+    pub log_level: String,
+    // Required to open the debug page or a control websocket; control is
+    // disabled entirely when unset
+    pub admin_token: Option<String>,
+}
+
+// One source of settings. Sources are layered over each other, with any value
+// left unset falling through to the next source and finally the defaults.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PartialConfig {
+    pub bind_address: Option<String>,
+    pub port: Option<u16>,
+    pub host_address: Option<String>,
+    pub public_url: Option<String>,
+    pub workers: Option<usize>,
+    pub simulation_threads: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub tick_rate_ms: Option<u64>,
+    pub max_catch_up_ticks: Option<u32>,
+    pub history_interval: Option<u64>,
+    pub history_length: Option<usize>,
+    pub food_cooldown_ms: Option<u64>,
+    pub seed: Option<u64>,
+    pub scenario: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub event_log: Option<PathBuf>,
+    pub log_format: Option<LogFormat>,
+    pub log_level: Option<String>,
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    HelpRequested,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { name: String, value: String },
+    File { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::HelpRequested => write!(f, "{}", USAGE),
+            ConfigError::UnknownFlag(flag) => write!(f, "Unknown option {}\n\n{}", flag, USAGE),
+            ConfigError::MissingValue(flag) => write!(f, "Missing value for {}", flag),
+            ConfigError::InvalidValue { name, value } => {
+                write!(f, "Invalid value {:?} for {}", value, name)
+            }
+            ConfigError::File { path, reason } => {
+                write!(
+                    f,
+                    "Unable to load config file {}: {}",
+                    path.display(),
+                    reason
+                )
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::resolve(&[])
+    }
+}
+
+impl Config {
+    // Reads flags, then the environment, then the config file named by either
+    pub fn load<I>(args: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let (config_path, from_args) = PartialConfig::from_args(args)?;
+        let from_env = PartialConfig::from_env(|key| env::var(key).ok())?;
+        let config_path = config_path.or_else(|| env::var("GARDEN_CONFIG").ok().map(PathBuf::from));
+        let from_file = match config_path {
+            Some(path) => PartialConfig::from_file(&path)?,
+            None => PartialConfig::default(),
+        };
+        let config = Config::resolve(&[from_args, from_env, from_file]);
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Earlier sources take precedence over later ones
+    pub fn resolve(sources: &[PartialConfig]) -> Config {
+        let mut merged = PartialConfig::default();
+        for source in sources.iter().rev() {
+            merged = source.clone().or(merged);
+        }
+        Config {
+            bind_address: merged
+                .bind_address
+                .unwrap_or_else(|| String::from(DEFAULT_BIND_ADDRESS)),
+            port: merged.port.unwrap_or(DEFAULT_PORT),
+            host_address: merged
+                .host_address
+                .unwrap_or_else(|| String::from(DEFAULT_HOST_ADDRESS)),
+            public_url: merged.public_url,
+            workers: merged.workers.unwrap_or(DEFAULT_WORKERS),
+            simulation_threads: merged
+                .simulation_threads
+                .unwrap_or_else(default_simulation_threads),
+            max_connections: merged.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            tick_rate_ms: merged.tick_rate_ms.unwrap_or(DEFAULT_TICK_RATE_MS),
+            max_catch_up_ticks: merged
+                .max_catch_up_ticks
+                .unwrap_or(DEFAULT_MAX_CATCH_UP_TICKS),
+            history_interval: merged.history_interval.unwrap_or(DEFAULT_HISTORY_INTERVAL),
+            history_length: merged.history_length.unwrap_or(DEFAULT_HISTORY_LENGTH),
+            food_cooldown_ms: merged.food_cooldown_ms.unwrap_or(DEFAULT_FOOD_COOLDOWN_MS),
+            seed: merged.seed,
+            scenario: merged.scenario,
+            snapshot: merged
+                .snapshot
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_SNAPSHOT)),
+            event_log: merged.event_log,
+            log_format: merged.log_format.unwrap_or(LogFormat::Text),
+            log_level: merged
+                .log_level
+                .unwrap_or_else(|| String::from(DEFAULT_LOG_LEVEL)),
+            admin_token: merged.admin_token.filter(|token| !token.is_empty()),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.workers == 0 {
+            return Err(invalid_value("workers", "0"));
+        }
+        if self.simulation_threads == 0 {
+            return Err(invalid_value("simulation_threads", "0"));
+        }
+        if self.tick_rate_ms == 0 {
+            return Err(invalid_value("tick_rate_ms", "0"));
+        }
+        if self.max_catch_up_ticks == 0 {
+            return Err(invalid_value("max_catch_up_ticks", "0"));
+        }
+        if self.history_interval == 0 {
+            return Err(invalid_value("history_interval", "0"));
+        }
+        if self.history_length == 0 {
+            return Err(invalid_value("history_length", "0"));
+        }
+        if self.log_level.parse::<LogLevels>().is_err() {
+            return Err(invalid_value("log_level", &self.log_level));
+        }
+        Ok(())
+    }
+
+    pub fn listen_address(&self) -> String {
+        format!("{}:{}", self.bind_address, self.port)
+    }
+
+    // Where browsers should open the websocket, derived from the public URL
+    // when there is one
+    pub fn websocket_url(&self) -> String {
+        format!("{}/websocket", self.websocket_base_url())
+    }
+
+    pub fn world_websocket_url(&self, world: &str) -> String {
+        format!("{}/worlds/{}/websocket", self.websocket_base_url(), world)
+    }
+
+    fn websocket_base_url(&self) -> String {
+        match &self.public_url {
+            Some(url) => {
+                let url = url.trim_end_matches('/');
+                if let Some(rest) = url.strip_prefix("https://") {
+                    format!("wss://{}", rest)
+                } else if let Some(rest) = url.strip_prefix("http://") {
+                    format!("ws://{}", rest)
+                } else {
+                    String::from(url)
+                }
+            }
+            None => format!("ws://{}:{}", self.host_address, self.port),
+        }
+    }
+
+    pub fn history(&self) -> History {
+        History::new(self.history_interval, self.history_length)
+    }
+
+    pub fn randomizer(&self) -> rand_pcg::Pcg32 {
+        randomizer(self.seed)
+    }
+}
+
+// Without a seed the world runs with the same randomizer it always has
+pub fn randomizer(seed: Option<u64>) -> rand_pcg::Pcg32 {
+    match seed {
+        Some(seed) => rand_pcg::Pcg32::seed_from_u64(seed),
+        None => rand_pcg::Pcg32::from_seed(*b"somebody once to"),
+    }
+}
+
+impl PartialConfig {
+    fn or(self, fallback: PartialConfig) -> PartialConfig {
+        PartialConfig {
+            bind_address: self.bind_address.or(fallback.bind_address),
+            port: self.port.or(fallback.port),
+            host_address: self.host_address.or(fallback.host_address),
+            public_url: self.public_url.or(fallback.public_url),
+            workers: self.workers.or(fallback.workers),
+            simulation_threads: self.simulation_threads.or(fallback.simulation_threads),
+            max_connections: self.max_connections.or(fallback.max_connections),
+            tick_rate_ms: self.tick_rate_ms.or(fallback.tick_rate_ms),
+            max_catch_up_ticks: self.max_catch_up_ticks.or(fallback.max_catch_up_ticks),
+            history_interval: self.history_interval.or(fallback.history_interval),
+            history_length: self.history_length.or(fallback.history_length),
+            food_cooldown_ms: self.food_cooldown_ms.or(fallback.food_cooldown_ms),
+            seed: self.seed.or(fallback.seed),
+            scenario: self.scenario.or(fallback.scenario),
+            snapshot: self.snapshot.or(fallback.snapshot),
+            event_log: self.event_log.or(fallback.event_log),
+            log_format: self.log_format.or(fallback.log_format),
+            log_level: self.log_level.or(fallback.log_level),
+            admin_token: self.admin_token.or(fallback.admin_token),
+        }
+    }
+
+    // Returns the config file path separately as it decides which file to read
+    pub fn from_args<I>(args: I) -> Result<(Option<PathBuf>, PartialConfig), ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config_path = None;
+        let mut partial = PartialConfig::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(ConfigError::HelpRequested);
+            }
+            let value = match flag.as_str() {
+                "--config"
+                | "--bind-address"
+                | "--port"
+                | "--host-address"
+                | "--public-url"
+                | "--workers"
+                | "--simulation-threads"
+                | "--max-connections"
+                | "--tick-rate-ms"
+                | "--max-catch-up-ticks"
+                | "--history-interval"
+                | "--history-length"
+                | "--food-cooldown-ms"
+                | "--seed"
+                | "--scenario"
+                | "--snapshot"
+                | "--event-log"
+                | "--log-format"
+                | "--log-level" => args
+                    .next()
+                    .ok_or_else(|| ConfigError::MissingValue(flag.clone()))?,
+                _ => return Err(ConfigError::UnknownFlag(flag)),
+            };
+            match flag.as_str() {
+                "--config" => config_path = Some(PathBuf::from(value)),
+                "--bind-address" => partial.bind_address = Some(value),
+                "--port" => partial.port = Some(parse_value(&flag, &value)?),
+                "--host-address" => partial.host_address = Some(value),
+                "--public-url" => partial.public_url = Some(value),
+                "--workers" => partial.workers = Some(parse_value(&flag, &value)?),
+                "--simulation-threads" => {
+                    partial.simulation_threads = Some(parse_value(&flag, &value)?)
+                }
+                "--max-connections" => partial.max_connections = Some(parse_value(&flag, &value)?),
+                "--tick-rate-ms" => partial.tick_rate_ms = Some(parse_value(&flag, &value)?),
+                "--max-catch-up-ticks" => {
+                    partial.max_catch_up_ticks = Some(parse_value(&flag, &value)?)
+                }
+                "--history-interval" => {
+                    partial.history_interval = Some(parse_value(&flag, &value)?)
+                }
+                "--history-length" => partial.history_length = Some(parse_value(&flag, &value)?),
+                "--food-cooldown-ms" => {
+                    partial.food_cooldown_ms = Some(parse_value(&flag, &value)?)
+                }
+                "--seed" => partial.seed = Some(parse_value(&flag, &value)?),
+                "--scenario" => partial.scenario = Some(PathBuf::from(value)),
+                "--snapshot" => partial.snapshot = Some(PathBuf::from(value)),
+                "--event-log" => partial.event_log = Some(PathBuf::from(value)),
+                "--log-format" => partial.log_format = Some(parse_value(&flag, &value)?),
+                "--log-level" => partial.log_level = Some(value),
+                _ => unreachable!(),
+            }
+        }
+        Ok((config_path, partial))
+    }
+
+    pub fn from_env<F>(var: F) -> Result<PartialConfig, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        Ok(PartialConfig {
+            bind_address: var("BIND_ADDRESS"),
+            port: parse_var(&var, "PORT")?,
+            host_address: var("HOST_ADDRESS"),
+            public_url: var("PUBLIC_URL"),
+            workers: parse_var(&var, "WORKERS")?,
+            simulation_threads: parse_var(&var, "SIMULATION_THREADS")?,
+            max_connections: parse_var(&var, "MAX_CONNECTIONS")?,
+            tick_rate_ms: parse_var(&var, "TICK_RATE_MS")?,
+            max_catch_up_ticks: parse_var(&var, "MAX_CATCH_UP_TICKS")?,
+            history_interval: parse_var(&var, "HISTORY_INTERVAL")?,
+            history_length: parse_var(&var, "HISTORY_LENGTH")?,
+            food_cooldown_ms: parse_var(&var, "FOOD_COOLDOWN_MS")?,
+            seed: parse_var(&var, "SEED")?,
+            scenario: var("SCENARIO").map(PathBuf::from),
+            snapshot: var("SNAPSHOT").map(PathBuf::from),
+            event_log: var("EVENT_LOG").map(PathBuf::from),
+            log_format: parse_var(&var, "LOG_FORMAT")?,
+            log_level: var("LOG_LEVEL").or_else(|| var("RUST_LOG")),
+            admin_token: var("ADMIN_TOKEN"),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<PartialConfig, ConfigError> {
+        let file_error = |reason: String| ConfigError::File {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let contents = fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+        serde_json::from_str(&contents).map_err(|e| file_error(e.to_string()))
+    }
+}
+
+fn default_simulation_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| invalid_value(name, value))
+}
+
+fn parse_var<T, F>(var: &F, key: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    F: Fn(&str) -> Option<String>,
+{
+    match var(key) {
+        Some(value) => Ok(Some(parse_value(key, &value)?)),
+        None => Ok(None),
+    }
+}
+
+fn invalid_value(name: &str, value: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        name: String::from(name),
+        value: String::from(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let (_, from_args) = PartialConfig::from_args(args(&["--port", "9000"])).unwrap();
+        let from_env = PartialConfig::from_env(|key| match key {
+            "PORT" => Some(String::from("8000")),
+            "WORKERS" => Some(String::from("8")),
+            _ => None,
+        })
+        .unwrap();
+        let from_file: PartialConfig =
+            serde_json::from_str(r#"{"port": 7000, "workers": 2, "seed": 42}"#).unwrap();
+
+        let config = Config::resolve(&[from_args, from_env, from_file]);
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.bind_address, DEFAULT_BIND_ADDRESS);
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(matches!(
+            PartialConfig::from_args(args(&["--port", "seventy"])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            PartialConfig::from_args(args(&["--workers"])),
+            Err(ConfigError::MissingValue(_))
+        ));
+        assert!(matches!(
+            PartialConfig::from_args(args(&["--debug"])),
+            Err(ConfigError::UnknownFlag(_))
+        ));
+        assert!(matches!(
+            PartialConfig::from_args(args(&["--log-format", "xml"])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        let (_, from_args) =
+            PartialConfig::from_args(args(&["--log-level", "garden=loud"])).unwrap();
+        assert!(matches!(
+            Config::resolve(&[from_args]).validate(),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_websocket_url() {
+        let mut config = Config::default();
+        assert_eq!(config.websocket_url(), "ws://localhost:7878/websocket");
+
+        config.public_url = Some(String::from("https://garden.example.com/"));
+        assert_eq!(config.websocket_url(), "wss://garden.example.com/websocket");
+        assert_eq!(
+            config.world_websocket_url("lab"),
+            "wss://garden.example.com/worlds/lab/websocket"
+        );
+    }
+}
@@ -1,21 +1,206 @@
-#[cfg(feature = "console-renderer")]
-
-// Console renderer
-use std::io;
-use console::Term;
-
-pub fn render(world: &World) -> io::Result<()>{
-    let mut lines: Vec<String> = Vec::new();
-    for y in 0..*world.get_height() {
-        let mut line = String::from("");
-        for x in 0..*world.get_width(){
-            if x == world.player.x && y == world.player.y {
-                line.push_str("░░");
-            } else {
-                line.push_str("██");
+// Console renderer, draws the default world in the terminal the server was
+// started from. Logs still go to stderr, so redirect it (`2>garden.log`) to
+// keep them from drawing over the world.
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::commands::Command;
+use crate::scheduler::{self, Speed};
+use crate::shutdown::Shutdown;
+use crate::simulation::{Frame, SharedWorld};
+
+// Also how long a key press can take to be noticed
+const REFRESH_INTERVAL_MS: u64 = 100;
+const EMPTY: Color = Color::Rgb {
+    r: 0,
+    g: 153,
+    b: 51,
+};
+
+pub const CONTROLS: &str = "[space] pause/resume  [s] step  [+/-] speed  [q] quit";
+
+// Runs until a shutdown is requested, or requests one when `q` is pressed
+pub fn run(world_ref: &Arc<SharedWorld>, shutdown: &Shutdown) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let _terminal = RawTerminal::enter(&mut stdout)?;
+    let mut drawn_tick = None;
+    while !shutdown.is_requested() {
+        let frame = world_ref.current();
+        // Redraw when paused too, the status bar shows the paused state
+        if drawn_tick != Some((frame.world.current_tick(), frame.world.is_active())) {
+            draw(&mut stdout, &frame)?;
+            drawn_tick = Some((frame.world.current_tick(), frame.world.is_active()));
+        }
+
+        if !event::poll(Duration::from_millis(REFRESH_INTERVAL_MS))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match command_for_key(key, &frame) {
+                Some(command) => world_ref.update(|w| crate::control_world(w, &command)),
+                None if is_quit(key) => shutdown.request(),
+                None => (),
             }
         }
-        lines.push(line);
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Raw mode and the alternate screen are undone however the renderer exits
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter(stdout: &mut io::Stdout) -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn draw(stdout: &mut io::Stdout, frame: &Frame) -> io::Result<()> {
+    let world = &frame.world;
+    // Like the web page, x goes down the screen and y across it
+    let rows = world.width.max(0) as usize;
+    let columns = world.height.max(0) as usize;
+    let mut cells = vec![EMPTY; rows * columns];
+    for (_, spec) in world.entity_specs() {
+        if let Some(position) = spec.position() {
+            let index = position.x as usize * columns + position.y as usize;
+            if let Some(cell) = cells.get_mut(index) {
+                *cell = kind_color(spec.kind());
+            }
+        }
+    }
+
+    queue!(stdout, Clear(ClearType::All))?;
+    for (x, row) in cells.chunks(columns.max(1)).enumerate() {
+        queue!(stdout, MoveTo(0, x as u16))?;
+        for color in row {
+            queue!(stdout, SetBackgroundColor(*color), Print("  "))?;
+        }
+        queue!(stdout, ResetColor)?;
+    }
+    queue!(
+        stdout,
+        MoveTo(0, rows as u16),
+        SetForegroundColor(Color::White),
+        Print(status_line(frame)),
+        MoveTo(0, rows as u16 + 1),
+        Print(CONTROLS),
+        ResetColor
+    )?;
+    stdout.flush()
+}
+
+fn kind_color(kind: &str) -> Color {
+    match kind {
+        "food" => Color::Rgb { r: 255, g: 0, b: 0 },
+        "eater" => Color::Rgb {
+            r: 153,
+            g: 102,
+            b: 0,
+        },
+        _ => Color::Black,
+    }
+}
+
+pub fn status_line(frame: &Frame) -> String {
+    let specs = frame.world.entity_specs();
+    let count = |kind| specs.iter().filter(|(_, spec)| spec.kind() == kind).count();
+    let state = if frame.world.is_active() {
+        match frame.speed {
+            Speed::Multiplier(multiplier) => format!("running at {}x", multiplier),
+            Speed::AsFastAsPossible => String::from("running as fast as possible"),
+        }
+    } else {
+        String::from("paused")
+    };
+    format!(
+        "tick {}  eaters {}  food {}  {}",
+        frame.world.current_tick(),
+        count("eater"),
+        count("food"),
+        state
+    )
+}
+
+// The same commands the debug page sends, so keys behave like its buttons
+fn command_for_key(key: KeyEvent, frame: &Frame) -> Option<Command> {
+    let multiplier = match frame.speed {
+        Speed::Multiplier(multiplier) => multiplier,
+        Speed::AsFastAsPossible => scheduler::MAX_SPEED,
+    };
+    match key.code {
+        KeyCode::Char(' ') if frame.world.is_active() => Some(Command::Pause),
+        KeyCode::Char(' ') => Some(Command::Resume),
+        KeyCode::Char('s') => Some(Command::Step { n: 1 }),
+        KeyCode::Char('+') | KeyCode::Char('=') => Some(Command::SetSpeed {
+            speed: Speed::Multiplier((multiplier * 2.0).min(scheduler::MAX_SPEED)),
+        }),
+        KeyCode::Char('-') => Some(Command::SetSpeed {
+            speed: Speed::Multiplier((multiplier / 2.0).max(scheduler::MIN_SPEED)),
+        }),
+        _ => None,
+    }
+}
+
+// Raw mode swallows Ctrl+C, so it's handled here rather than as a signal
+fn is_quit(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char('q')
+        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::History;
+    use crate::simulation::ConfiguredWorld;
+    use crate::world::World;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn test_keys_control_the_world() {
+        let world_ref = SharedWorld::new(ConfiguredWorld::new(
+            World::default(),
+            100,
+            rand_pcg::Pcg32::seed_from_u64(1),
+            History::new(10, 10),
+        ));
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        let frame = world_ref.current();
+        assert_eq!(
+            status_line(&frame),
+            "tick 0  eaters 1  food 1  running at 1x"
+        );
+
+        assert_eq!(command_for_key(key(' '), &frame), Some(Command::Pause));
+        assert_eq!(
+            command_for_key(key('-'), &frame),
+            Some(Command::SetSpeed {
+                speed: Speed::Multiplier(0.5)
+            })
+        );
+        world_ref.update(|w| crate::control_world(w, &Command::Pause));
+        let frame = world_ref.current();
+        assert_eq!(command_for_key(key(' '), &frame), Some(Command::Resume));
+        assert!(status_line(&frame).ends_with("paused"));
+        assert!(is_quit(key('q')));
+    }
+}
@@ -0,0 +1,155 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use tungstenite::handshake::server::Request;
+use tungstenite::protocol::Message;
+
+use crate::world::{palette_index, Position, RenderedEntity};
+
+// Clients opt into binary frames by offering this subprotocol or by
+// connecting with `?encoding=binary`. Anything else gets JSON.
+pub const BINARY_SUBPROTOCOL: &str = "garden.binary.v1";
+const BINARY_QUERY: &str = "encoding=binary";
+
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+// x: u16, y: u16, palette index: u8
+pub const BINARY_RECORD_SIZE: usize = 5;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Json(serde_json::Error),
+    // Not in `world::PALETTE`
+    UnknownColor(String),
+    // Doesn't fit the binary format's u16 coordinates
+    OutOfRange(Position),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Json(e) => write!(f, "{}", e),
+            EncodeError::UnknownColor(color) => {
+                write!(f, "Color {} has no palette index", color)
+            }
+            EncodeError::OutOfRange(position) => write!(
+                f,
+                "Cell ({}, {}) is out of range for the binary format",
+                position.x, position.y
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    pub fn negotiate(request: &Request) -> Encoding {
+        let queried_binary = match request.uri().query() {
+            Some(query) => query.split('&').any(|pair| pair == BINARY_QUERY),
+            None => false,
+        };
+
+        if offers_binary_subprotocol(request) || queried_binary {
+            Encoding::Binary
+        } else {
+            Encoding::Json
+        }
+    }
+
+    pub fn encode(&self, rendered_entities: &[RenderedEntity]) -> Result<Message, EncodeError> {
+        match self {
+            Encoding::Json => serde_json::to_string(rendered_entities)
+                .map(Message::text)
+                .map_err(EncodeError::Json),
+            Encoding::Binary => encode_binary(rendered_entities).map(Message::binary),
+        }
+    }
+}
+
+// Browsers drop the connection unless the server echoes back a subprotocol
+// they offered, so the handshake needs to know this separately from `negotiate`
+pub fn offers_binary_subprotocol(request: &Request) -> bool {
+    request
+        .headers()
+        .get_all("Sec-WebSocket-Protocol")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == BINARY_SUBPROTOCOL)
+}
+
+// Layout: one version byte followed by a fixed-width little-endian record per
+// entity. Colors are sent as an index into `world::PALETTE`.
+pub fn encode_binary(rendered_entities: &[RenderedEntity]) -> Result<Vec<u8>, EncodeError> {
+    let mut buffer = Vec::with_capacity(1 + rendered_entities.len() * BINARY_RECORD_SIZE);
+    buffer.push(BINARY_FORMAT_VERSION);
+    for entity in rendered_entities {
+        let position = entity.position;
+        let coordinate =
+            |value: i32| u16::try_from(value).map_err(|_| EncodeError::OutOfRange(position));
+        let color = palette_index(&entity.color)
+            .ok_or_else(|| EncodeError::UnknownColor(entity.color.clone()))?;
+        buffer.extend_from_slice(&coordinate(position.x)?.to_le_bytes());
+        buffer.extend_from_slice(&coordinate(position.y)?.to_le_bytes());
+        buffer.push(color);
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{BROWN, RED};
+
+    #[test]
+    fn test_encode_binary() {
+        let rendered_entities = vec![
+            RenderedEntity {
+                position: Position { x: 1, y: 2 },
+                color: String::from(RED),
+            },
+            RenderedEntity {
+                position: Position { x: 300, y: 0 },
+                color: String::from(BROWN),
+            },
+        ];
+
+        let encoded = encode_binary(&rendered_entities).unwrap();
+
+        assert_eq!(encoded.len(), 1 + 2 * BINARY_RECORD_SIZE);
+        assert_eq!(encoded[0], BINARY_FORMAT_VERSION);
+        assert_eq!(&encoded[1..6], &[1, 0, 2, 0, 1]);
+        assert_eq!(&encoded[6..11], &[44, 1, 0, 0, 2]);
+
+        // Refused rather than sent as the wrong cell or color
+        let too_far = RenderedEntity {
+            position: Position { x: 70_000, y: 0 },
+            color: String::from(RED),
+        };
+        assert!(encode_binary(&[too_far]).is_err());
+        let unknown_color = RenderedEntity {
+            position: Position { x: 0, y: 0 },
+            color: String::from("#123456"),
+        };
+        assert!(encode_binary(&[unknown_color]).is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let json_request = Request::get("/websocket").body(()).unwrap();
+        assert_eq!(Encoding::negotiate(&json_request), Encoding::Json);
+
+        let query_request = Request::get("/websocket?encoding=binary").body(()).unwrap();
+        assert_eq!(Encoding::negotiate(&query_request), Encoding::Binary);
+
+        let subprotocol_request = Request::get("/websocket")
+            .header("Sec-WebSocket-Protocol", "chat, garden.binary.v1")
+            .body(())
+            .unwrap();
+        assert_eq!(Encoding::negotiate(&subprotocol_request), Encoding::Binary);
+    }
+}
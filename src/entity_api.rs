@@ -0,0 +1,222 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::auth::Role;
+use crate::history::Input;
+use crate::http::{self, HttpRequest};
+use crate::logging;
+use crate::simulation::SharedWorld;
+use crate::world::scenario::EntitySpec;
+use crate::world::{EntityError, EntityId, EntityState, Position, World};
+
+// What `GET /worlds/{name}/entities` lists for each entity
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntitySummary {
+    pub id: EntityId,
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+}
+
+// Working out an entity's details can mean pathfinding, so listings that
+// include them are kept short
+pub const MAX_DETAILED_ENTITIES: usize = 50;
+
+// `?kind=eater&region=x0,y0,x1,y1`, the region's corners are inclusive.
+// Entities without a position are left out when filtering by region.
+// `&details=true` lists each entity's full state instead of a summary.
+#[derive(Debug, Default, PartialEq)]
+pub struct EntityFilter {
+    kind: Option<String>,
+    region: Option<(Position, Position)>,
+    details: bool,
+}
+
+// Serves `/worlds/{name}/entities` and `/worlds/{name}/entities/{id}`. Reads
+// are answered from the latest frame; changes are checked against it and then
+// queued for the next tick boundary, so they're answered with 202 Accepted.
+pub fn handle<W: Write>(
+    stream: &mut W,
+    request: &HttpRequest,
+    role: Role,
+    shared: &SharedWorld,
+    id: Option<&str>,
+) {
+    let id = match id.map(str::parse::<EntityId>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => return http::respond_error(stream, "404 Not Found", "No such entity"),
+        None => None,
+    };
+    let _entity = id.map(|id| logging::field("entity_id", id));
+    if request.method != "GET" && !role.is_admin() {
+        log::warn!(
+            "Rejected {} {} without an admin token",
+            request.method,
+            request.path
+        );
+        let message = "Changing entities requires an admin token";
+        return http::respond_error(stream, "403 Forbidden", message);
+    }
+
+    let frame = shared.current();
+    let result = match (request.method.as_str(), id) {
+        ("GET", None) => EntityFilter::parse(request).and_then(|filter| {
+            let body = if filter.details {
+                serde_json::to_string(&list_details(&frame.world, &filter)?)
+            } else {
+                serde_json::to_string(&list(&frame.world, &filter))
+            };
+            Ok(("200 OK", body.unwrap()))
+        }),
+        ("GET", Some(id)) => match frame.world.entity_state(id) {
+            Some(state) => Ok(("200 OK", serde_json::to_string(&state).unwrap())),
+            None => Err(EntityError::NotFound(id)),
+        },
+        ("POST", None) => serde_json::from_slice(&request.body)
+            .map_err(|e| EntityError::Invalid(e.to_string()))
+            .map(|entity| Input::Spawn { entity })
+            .and_then(|input| queue(shared, input)),
+        ("DELETE", Some(id)) => queue(shared, Input::Remove { id }),
+        ("PATCH", Some(id)) => serde_json::from_slice(&request.body)
+            .map_err(|e| EntityError::Invalid(e.to_string()))
+            .map(|changes| Input::Patch { id, changes })
+            .and_then(|input| queue(shared, input)),
+        _ => {
+            let message = format!("Unsupported method {}", request.method);
+            return http::respond_error(stream, "405 Method Not Allowed", &message);
+        }
+    };
+    match result {
+        Ok((status, body)) => http::respond(stream, status, "application/json", &body),
+        Err(e) => {
+            log::warn!("{} {}: {}", request.method, request.path, e);
+            let status = match e {
+                EntityError::NotFound(_) => "404 Not Found",
+                EntityError::Occupied(_) => "409 Conflict",
+                EntityError::Invalid(_) => "400 Bad Request",
+            };
+            http::respond_error(stream, status, &e.to_string())
+        }
+    }
+}
+
+pub fn list(world: &World, filter: &EntityFilter) -> Vec<EntitySummary> {
+    world
+        .entity_specs()
+        .into_iter()
+        .filter(|(_, spec)| filter.matches(spec))
+        .map(|(id, spec)| EntitySummary {
+            id,
+            kind: spec.kind(),
+            position: spec.position(),
+        })
+        .collect()
+}
+
+pub fn list_details(world: &World, filter: &EntityFilter) -> Result<Vec<EntityState>, EntityError> {
+    let ids: Vec<EntityId> = list(world, filter)
+        .into_iter()
+        .map(|summary| summary.id)
+        .collect();
+    if ids.len() > MAX_DETAILED_ENTITIES {
+        return Err(EntityError::Invalid(format!(
+            "Details are only listed for up to {} entities, found {}; narrow the region",
+            MAX_DETAILED_ENTITIES,
+            ids.len()
+        )));
+    }
+    Ok(ids
+        .into_iter()
+        .filter_map(|id| world.entity_state(id))
+        .collect())
+}
+
+fn queue(shared: &SharedWorld, input: Input) -> Result<(&'static str, String), EntityError> {
+    shared.check_input(&input)?;
+    let body = serde_json::json!({ "queued": input }).to_string();
+    shared.update(|w| w.queue_input(input));
+    Ok(("202 Accepted", body))
+}
+
+impl EntityFilter {
+    pub fn parse(request: &HttpRequest) -> Result<EntityFilter, EntityError> {
+        let region = match request.query_param("region") {
+            Some(region) => Some(parse_region(region).ok_or_else(|| {
+                EntityError::Invalid(format!(
+                    "Region {:?} should be four numbers, x0,y0,x1,y1",
+                    region
+                ))
+            })?),
+            None => None,
+        };
+        Ok(EntityFilter {
+            kind: request.query_param("kind").map(String::from),
+            region,
+            details: request.query_param("details") == Some("true"),
+        })
+    }
+
+    fn matches(&self, spec: &EntitySpec) -> bool {
+        let kind_matches = self.kind.as_ref().is_none_or(|kind| kind == spec.kind());
+        let region_matches = match (self.region, spec.position()) {
+            (None, _) => true,
+            (Some((min, max)), Some(position)) => {
+                (min.x..=max.x).contains(&position.x) && (min.y..=max.y).contains(&position.y)
+            }
+            (Some(_), None) => false,
+        };
+        kind_matches && region_matches
+    }
+}
+
+// Corners can be given in any order
+fn parse_region(region: &str) -> Option<(Position, Position)> {
+    let numbers = region
+        .split(',')
+        .map(|n| n.trim().parse::<i32>().ok())
+        .collect::<Option<Vec<i32>>>()?;
+    match numbers[..] {
+        [x0, y0, x1, y1] => Some((
+            Position {
+                x: x0.min(x1),
+                y: y0.min(y1),
+            },
+            Position {
+                x: x0.max(x1),
+                y: y0.max(y1),
+            },
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::scenario::Scenario;
+
+    #[test]
+    fn test_filter_by_kind_and_region() {
+        let world = Scenario::default().build_world();
+        let raw = b"GET /worlds/default/entities?kind=eater&region=20,20,10,10 HTTP/1.1\r\n\r\n";
+        let filter = EntityFilter::parse(&HttpRequest::read(&mut &raw[..]).unwrap()).unwrap();
+
+        assert_eq!(
+            list(&world, &filter),
+            vec![EntitySummary {
+                id: 2,
+                kind: "eater",
+                position: Some(Position { x: 15, y: 15 }),
+            }]
+        );
+        assert_eq!(list(&world, &EntityFilter::default()).len(), 4);
+
+        let raw = b"GET /worlds/default/entities?region=15,15,15,15&details=true HTTP/1.1\r\n\r\n";
+        let filter = EntityFilter::parse(&HttpRequest::read(&mut &raw[..]).unwrap()).unwrap();
+        let detailed = list_details(&world, &filter).unwrap();
+        assert_eq!(detailed.len(), 1);
+        assert_eq!(detailed[0].spec.kind(), "eater");
+        assert!(!detailed[0].details.desires.is_empty());
+    }
+}
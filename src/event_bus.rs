@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::shutdown::Shutdown;
+use crate::world::events::WorldEvent;
+
+// Ticks of events kept for readers that fall behind
+pub const EVENT_BUS_TICKS: usize = 256;
+// How often the event log picks up new events
+const LOG_INTERVAL_MS: u64 = 100;
+
+// A tick's events as published by the tick thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickEvents {
+    pub tick: u64,
+    pub events: Arc<Vec<WorldEvent>>,
+}
+
+// Hands each tick's events to any number of readers. Every reader keeps its
+// own cursor and reads at its own pace, publishing never waits on them. A
+// reader that falls more than the bus's capacity behind misses ticks.
+pub struct EventBus {
+    capacity: usize,
+    state: Mutex<BusState>,
+}
+
+struct BusState {
+    // Sequence number of the next tick published
+    next: u64,
+    ticks: VecDeque<TickEvents>,
+}
+
+// Sequence number of the next tick a reader hasn't seen
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EventCursor(u64);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Received {
+    pub ticks: Vec<TickEvents>,
+    // Ticks that dropped off the bus before they were read
+    pub missed: u64,
+}
+
+// One line of the event log
+#[derive(Serialize)]
+struct LoggedEvent<'a> {
+    tick: u64,
+    #[serde(flatten)]
+    event: &'a WorldEvent,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> EventBus {
+        EventBus {
+            capacity,
+            state: Mutex::new(BusState {
+                next: 0,
+                ticks: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    pub fn publish(&self, tick: u64, events: Arc<Vec<WorldEvent>>) {
+        let mut state = self.state.lock().unwrap();
+        if state.ticks.len() == self.capacity {
+            state.ticks.pop_front();
+        }
+        state.ticks.push_back(TickEvents { tick, events });
+        state.next += 1;
+    }
+
+    // A cursor that only sees what's published from now on
+    pub fn cursor(&self) -> EventCursor {
+        EventCursor(self.state.lock().unwrap().next)
+    }
+
+    // Everything published since the cursor last read, moving it up to date
+    pub fn read(&self, cursor: &mut EventCursor) -> Received {
+        let state = self.state.lock().unwrap();
+        let oldest = state.next - state.ticks.len() as u64;
+        let missed = oldest.saturating_sub(cursor.0);
+        let skip = cursor.0.saturating_sub(oldest) as usize;
+        cursor.0 = state.next;
+        Received {
+            ticks: state.ticks.iter().skip(skip).cloned().collect(),
+            missed,
+        }
+    }
+}
+
+// Appends the bus's events to a JSON Lines file, one event per line, until a
+// shutdown is requested
+pub fn write_log(bus: &EventBus, path: &Path, shutdown: &Shutdown) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut out = BufWriter::new(file);
+    let mut cursor = bus.cursor();
+    loop {
+        // Checked first so the last events are written after a shutdown
+        let stopping = shutdown.is_requested();
+        let received = bus.read(&mut cursor);
+        if received.missed > 0 {
+            log::warn!("Event log fell behind, missed {} ticks", received.missed);
+        }
+        for tick in received.ticks.iter() {
+            for event in tick.events.iter() {
+                let line = LoggedEvent {
+                    tick: tick.tick,
+                    event,
+                };
+                serde_json::to_writer(&mut out, &line)?;
+                writeln!(out)?;
+            }
+        }
+        out.flush()?;
+        if stopping {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(LOG_INTERVAL_MS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(received: &Received) -> Vec<u64> {
+        received.ticks.iter().map(|tick| tick.tick).collect()
+    }
+
+    #[test]
+    fn test_readers_keep_their_own_place() {
+        let bus = EventBus::new(3);
+        let moved = Arc::new(vec![WorldEvent::Ate { eater: 1, food: 2 }]);
+        bus.publish(1, Arc::clone(&moved));
+        let mut early = bus.cursor();
+        let mut late = bus.cursor();
+        bus.publish(2, Arc::clone(&moved));
+        bus.publish(3, Arc::clone(&moved));
+
+        assert_eq!(ticks(&bus.read(&mut early)), vec![2, 3]);
+        assert_eq!(bus.read(&mut early), Received::default());
+
+        bus.publish(4, Arc::clone(&moved));
+        bus.publish(5, Arc::clone(&moved));
+        bus.publish(6, Arc::clone(&moved));
+        let received = bus.read(&mut late);
+        assert_eq!(ticks(&received), vec![4, 5, 6]);
+        assert_eq!(received.missed, 2);
+        assert_eq!(ticks(&bus.read(&mut early)), vec![4, 5, 6]);
+    }
+}
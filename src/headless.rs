@@ -0,0 +1,243 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::config;
+use crate::stats::{StatsFormat, StatsWriter, TickStats};
+use crate::thread_pool::ThreadPool;
+use crate::world::scenario::{Scenario, ScenarioError};
+use crate::world::World;
+
+pub const USAGE: &str = "Usage: garden-sim [OPTIONS]
+
+Runs a world without the web server, as fast as possible, and writes
+statistics for every tick.
+
+Options:
+    --scenario <PATH>        Scenario to run (default: the default world)
+    --ticks <N>              Ticks to run (default 1000)
+    --seed <N>               Seed for the world's randomizer
+    --format <csv|jsonl>     Output format (default: from the output's extension, else csv)
+    --output <PATH>          File to write statistics to (default: stdout)
+    --simulation-threads <N> Threads used to update the world (default 1)
+
+`garden-sim sweep --help` describes running many variations of a scenario.";
+
+const DEFAULT_TICKS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimOptions {
+    pub scenario: Option<PathBuf>,
+    pub ticks: u64,
+    pub seed: Option<u64>,
+    pub format: StatsFormat,
+    pub output: Option<PathBuf>,
+    // Results are the same whatever this is set to
+    pub simulation_threads: usize,
+}
+
+#[derive(Debug)]
+pub enum SimError {
+    HelpRequested,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { name: String, value: String },
+    // See `sweep::MAX_COMBINATIONS`
+    TooManyCombinations,
+    // The tick that couldn't be planned, the log says why
+    TickSkipped(u64),
+    Scenario(ScenarioError),
+    Output(io::Error),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::HelpRequested => write!(f, "{}", USAGE),
+            SimError::UnknownFlag(flag) => write!(f, "Unknown option {}, see --help", flag),
+            SimError::MissingValue(flag) => write!(f, "Missing value for {}", flag),
+            SimError::InvalidValue { name, value } => {
+                write!(f, "Invalid value for {}: {:?}", name, value)
+            }
+            SimError::TooManyCombinations => write!(
+                f,
+                "More than {} combinations, sweep fewer values",
+                crate::sweep::MAX_COMBINATIONS
+            ),
+            SimError::TickSkipped(tick) => {
+                write!(f, "Unable to simulate tick {}, stopped before it", tick)
+            }
+            SimError::Scenario(e) => write!(f, "{}", e),
+            SimError::Output(e) => write!(f, "Unable to write statistics: {}", e),
+        }
+    }
+}
+
+impl SimOptions {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<SimOptions, SimError> {
+        let mut options = SimOptions {
+            scenario: None,
+            ticks: DEFAULT_TICKS,
+            seed: None,
+            format: StatsFormat::Csv,
+            output: None,
+            simulation_threads: 1,
+        };
+        let mut format = None;
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(SimError::HelpRequested);
+            }
+            let value = match flag.as_str() {
+                "--scenario"
+                | "--ticks"
+                | "--seed"
+                | "--format"
+                | "--output"
+                | "--simulation-threads" => args
+                    .next()
+                    .ok_or_else(|| SimError::MissingValue(flag.clone()))?,
+                _ => return Err(SimError::UnknownFlag(flag)),
+            };
+            match flag.as_str() {
+                "--scenario" => options.scenario = Some(PathBuf::from(value)),
+                "--ticks" => options.ticks = parse_value(&flag, &value)?,
+                "--seed" => options.seed = Some(parse_value(&flag, &value)?),
+                "--format" => format = Some(parse_format(&flag, &value)?),
+                "--output" => options.output = Some(PathBuf::from(value)),
+                "--simulation-threads" => {
+                    options.simulation_threads = parse_value(&flag, &value)?;
+                    if options.simulation_threads == 0 {
+                        return Err(invalid_value(&flag, &value));
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        options.format = StatsFormat::for_output(format, options.output.as_deref());
+        Ok(options)
+    }
+
+    pub fn load_world(&self) -> Result<World, SimError> {
+        match &self.scenario {
+            Some(path) => Scenario::load(path)
+                .map(|scenario| scenario.build_world())
+                .map_err(SimError::Scenario),
+            None => Ok(World::default()),
+        }
+    }
+}
+
+// Loads the scenario, runs it and writes a line of statistics for the
+// starting state and every tick after it
+pub fn run(options: &SimOptions) -> Result<(), SimError> {
+    let mut world = options.load_world()?;
+    let out: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(File::create(path).map_err(SimError::Output)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = StatsWriter::new(BufWriter::new(out), options.format);
+    let pool = ThreadPool::new(options.simulation_threads);
+    let mut randomizer = config::randomizer(options.seed);
+
+    let mut result = Ok(());
+    simulate(&mut world, &mut randomizer, options.ticks, &pool, |stats| {
+        if result.is_ok() {
+            result = writer.write(stats);
+        }
+    })?;
+    result
+        .and_then(|()| writer.flush())
+        .map_err(SimError::Output)
+}
+
+// `on_tick` also gets the statistics for the starting state. Stops at the
+// first tick that can't be planned, without reporting it.
+pub fn simulate<F>(
+    world: &mut World,
+    randomizer: &mut rand_pcg::Pcg32,
+    ticks: u64,
+    pool: &ThreadPool,
+    mut on_tick: F,
+) -> Result<(), SimError>
+where
+    F: FnMut(&TickStats),
+{
+    on_tick(&TickStats::of(world));
+    for _ in 0..ticks {
+        if !world.update_parallel(randomizer, pool) {
+            return Err(SimError::TickSkipped(world.current_tick() + 1));
+        }
+        on_tick(&TickStats::of(world));
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_format(name: &str, value: &str) -> Result<StatsFormat, SimError> {
+    match value {
+        "csv" => Ok(StatsFormat::Csv),
+        "jsonl" => Ok(StatsFormat::JsonLines),
+        _ => Err(invalid_value(name, value)),
+    }
+}
+
+pub(crate) fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, SimError> {
+    value.parse().map_err(|_| invalid_value(name, value))
+}
+
+pub(crate) fn invalid_value(name: &str, value: &str) -> SimError {
+    SimError::InvalidValue {
+        name: String::from(name),
+        value: String::from(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| String::from(*arg))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options =
+            SimOptions::from_args(args(&["--ticks", "50", "--output", "out.jsonl"])).unwrap();
+        assert_eq!(options.ticks, 50);
+        assert_eq!(options.format, StatsFormat::JsonLines);
+
+        let options =
+            SimOptions::from_args(args(&["--format", "csv", "--output", "out.jsonl"])).unwrap();
+        assert_eq!(options.format, StatsFormat::Csv);
+        assert!(matches!(
+            SimOptions::from_args(args(&["--format", "xml"])),
+            Err(SimError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            SimOptions::from_args(args(&["--ticks"])),
+            Err(SimError::MissingValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_simulate_reports_every_tick() {
+        let mut world = World::default();
+        let mut randomizer = config::randomizer(Some(4));
+        let mut ticks = vec![];
+        simulate(
+            &mut world,
+            &mut randomizer,
+            20,
+            &ThreadPool::new(2),
+            |stats| ticks.push(stats.tick),
+        )
+        .unwrap();
+        assert_eq!(ticks, (0..=20).collect::<Vec<u64>>());
+    }
+}
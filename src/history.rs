@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::scenario::EntitySpec;
+use crate::world::{EntityError, EntityId, World};
+
+// Changes made to the world from outside the simulation, applied between
+// ticks. They're logged so replaying a past tick reproduces them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "input", rename_all = "snake_case")]
+pub enum Input {
+    Spawn {
+        entity: EntitySpec,
+    },
+    Remove {
+        id: EntityId,
+    },
+    // `changes` uses the field names of the entity's scenario format
+    Patch {
+        id: EntityId,
+        changes: serde_json::Map<String, serde_json::Value>,
+    },
+}
+
+impl Input {
+    // Leaves the world untouched if the input no longer makes sense, e.g. the
+    // entity it's for died since it was queued
+    pub fn apply(&self, world: &mut World) -> Result<(), EntityError> {
+        match self {
+            Input::Spawn { entity } => world.spawn_entity(entity).map(|_| ()),
+            Input::Remove { id } => world.remove_entity(*id),
+            Input::Patch { id, changes } => {
+                let current = world
+                    .entity_state(*id)
+                    .ok_or(EntityError::NotFound(*id))?
+                    .spec;
+                world.replace_entity(*id, &current.patched(changes)?)
+            }
+        }
+    }
+}
+
+// The world and randomizer as they were straight after a tick, before any
+// inputs for that tick were applied
+#[derive(Clone)]
+struct Checkpoint {
+    world: World,
+    randomizer: rand_pcg::Pcg32,
+}
+
+// A bounded record of the past. The world is checkpointed every `interval`
+// ticks and everything in between is recreated by replaying ticks from the
+// nearest earlier checkpoint, which is deterministic as long as the inputs
+// applied along the way are replayed too.
+pub struct History {
+    interval: u64,
+    capacity: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    // Ordered by the tick they were applied at
+    inputs: VecDeque<(u64, Input)>,
+}
+
+// Everything needed to recreate a past tick, taken out of the history so the
+// replay doesn't hold up the simulation
+pub struct Replay {
+    checkpoint: Checkpoint,
+    inputs: Vec<(u64, Input)>,
+    tick: u64,
+}
+
+impl History {
+    pub fn new(interval: u64, capacity: usize) -> History {
+        History {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            checkpoints: VecDeque::new(),
+            inputs: VecDeque::new(),
+        }
+    }
+
+    // Called after every tick, keeps a checkpoint every `interval` ticks
+    pub fn record_tick(&mut self, world: &World, randomizer: &rand_pcg::Pcg32) {
+        let tick = world.current_tick();
+        let is_first = self.checkpoints.is_empty();
+        if !is_first && !tick.is_multiple_of(self.interval) {
+            return;
+        }
+        self.checkpoints.push_back(Checkpoint {
+            world: world.clone(),
+            randomizer: randomizer.clone(),
+        });
+        if self.checkpoints.len() > self.capacity {
+            self.checkpoints.pop_front();
+            let oldest = self.oldest_tick().unwrap_or(0);
+            while matches!(self.inputs.front(), Some((tick, _)) if *tick < oldest) {
+                self.inputs.pop_front();
+            }
+        }
+    }
+
+    pub fn record_input(&mut self, tick: u64, input: Input) {
+        self.inputs.push_back((tick, input));
+    }
+
+    pub fn oldest_tick(&self) -> Option<u64> {
+        self.checkpoints
+            .front()
+            .map(|checkpoint| checkpoint.world.current_tick())
+    }
+
+    // None if the tick is older than the oldest checkpoint
+    pub fn replay_to(&self, tick: u64) -> Option<Replay> {
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.world.current_tick() <= tick)?;
+        let from = checkpoint.world.current_tick();
+        let inputs = self
+            .inputs
+            .iter()
+            .filter(|(input_tick, _)| *input_tick >= from && *input_tick < tick)
+            .cloned()
+            .collect();
+        Some(Replay {
+            checkpoint: checkpoint.clone(),
+            inputs,
+            tick,
+        })
+    }
+
+    // Forgets everything after `tick`, used when the simulation is forked from it
+    pub fn truncate_after(&mut self, tick: u64) {
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.world.current_tick() <= tick);
+        self.inputs.retain(|(input_tick, _)| *input_tick < tick);
+    }
+}
+
+impl Replay {
+    // The world and randomizer as of straight after `tick`
+    pub fn run(self) -> (World, rand_pcg::Pcg32) {
+        let Checkpoint {
+            mut world,
+            mut randomizer,
+        } = self.checkpoint;
+        let mut inputs = self.inputs.iter().peekable();
+        while world.current_tick() < self.tick {
+            while let Some((_, input)) =
+                inputs.next_if(|(input_tick, _)| *input_tick == world.current_tick())
+            {
+                // Only inputs that applied cleanly are recorded
+                let _ = input.apply(&mut world);
+            }
+            world.update(&mut randomizer);
+        }
+        (world, randomizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Position;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn test_replay_matches_the_original_run() {
+        let mut world = World::default();
+        let mut randomizer = rand_pcg::Pcg32::seed_from_u64(9);
+        let mut history = History::new(10, 3);
+        history.record_tick(&world, &randomizer);
+
+        let mut past = vec![];
+        for _ in 0..45 {
+            world.update(&mut randomizer);
+            history.record_tick(&world, &randomizer);
+            past.push(world.snapshot());
+        }
+
+        // Only the last three checkpoints (20, 30, 40) are kept
+        assert_eq!(history.oldest_tick(), Some(20));
+        assert!(history.replay_to(15).is_none());
+        for tick in [20, 27, 44, 45] {
+            let (replayed, _) = history.replay_to(tick).unwrap().run();
+            assert_eq!(replayed.snapshot(), past[tick as usize - 1]);
+        }
+    }
+
+    #[test]
+    fn test_replay_includes_inputs() {
+        let mut world = World::new(5, 5);
+        let mut randomizer = rand_pcg::Pcg32::seed_from_u64(9);
+        let mut history = History::new(10, 3);
+        history.record_tick(&world, &randomizer);
+
+        let input = Input::Spawn {
+            entity: EntitySpec::Food {
+                position: Position { x: 1, y: 1 },
+            },
+        };
+        input.apply(&mut world).unwrap();
+        history.record_input(0, input);
+        world.update(&mut randomizer);
+
+        let (replayed, _) = history.replay_to(1).unwrap().run();
+        assert_eq!(replayed.snapshot(), world.snapshot());
+        // Inputs at the target tick haven't happened yet as of that tick
+        let (replayed, _) = history.replay_to(0).unwrap().run();
+        assert!(replayed.snapshot().entities.is_empty());
+    }
+}
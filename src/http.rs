@@ -0,0 +1,246 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+// Plenty for a scenario or an entity patch
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+const MAX_HEADERS: usize = 32;
+// How long a client gets to send the whole request, however it trickles in
+const REQUEST_DEADLINE_MS: u64 = 10_000;
+
+// A fully read request, for routes that need more than the first 512 bytes
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Io(io::Error),
+    Malformed(String),
+    TooLarge(usize),
+    TimedOut,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Io(e) => write!(f, "Unable to read request: {}", e),
+            HttpError::Malformed(reason) => write!(f, "Malformed request: {}", reason),
+            HttpError::TooLarge(size) => write!(
+                f,
+                "Request body of {} bytes is over the {} byte limit",
+                size, MAX_BODY_SIZE
+            ),
+            HttpError::TimedOut => write!(f, "Request was not sent in time"),
+        }
+    }
+}
+
+impl HttpError {
+    // The status line to answer with, e.g. "400 Bad Request"
+    pub fn status(&self) -> &'static str {
+        match self {
+            HttpError::TooLarge(_) => "413 Payload Too Large",
+            HttpError::TimedOut => "408 Request Timeout",
+            HttpError::Io(_) | HttpError::Malformed(_) => "400 Bad Request",
+        }
+    }
+}
+
+impl HttpRequest {
+    pub fn read<R: Read>(stream: &mut R) -> Result<HttpRequest, HttpError> {
+        let deadline = Instant::now() + Duration::from_millis(REQUEST_DEADLINE_MS);
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let read = read_before(stream, &mut chunk, deadline)?;
+            if read == 0 {
+                return Err(HttpError::Malformed(String::from("connection closed")));
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut parsed = httparse::Request::new(&mut headers);
+            let header_length = match parsed.parse(&buffer) {
+                Ok(httparse::Status::Complete(length)) => length,
+                Ok(httparse::Status::Partial) if buffer.len() < MAX_BODY_SIZE => continue,
+                Ok(httparse::Status::Partial) => return Err(HttpError::TooLarge(buffer.len())),
+                Err(e) => return Err(HttpError::Malformed(e.to_string())),
+            };
+
+            let (path, query) = match parsed.path.unwrap_or("/").split_once('?') {
+                Some((path, query)) => (String::from(path), Some(String::from(query))),
+                None => (String::from(parsed.path.unwrap_or("/")), None),
+            };
+            let mut request = HttpRequest {
+                method: String::from(parsed.method.unwrap_or("GET")),
+                path,
+                query,
+                headers: parsed
+                    .headers
+                    .iter()
+                    .map(|header| {
+                        (
+                            header.name.to_ascii_lowercase(),
+                            String::from_utf8_lossy(header.value).into_owned(),
+                        )
+                    })
+                    .collect(),
+                body: buffer[header_length..].to_vec(),
+            };
+            request.read_body(stream, deadline)?;
+            return Ok(request);
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Values aren't percent-decoded, none of the parameters used need it
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    // Path segments after the leading slash, e.g. ["worlds", "lab"]
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    // The admin token from `Authorization: Bearer <token>` or `?token=<token>`
+    pub fn token(&self) -> Option<&str> {
+        self.header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| crate::auth::token_from_query(self.query.as_deref()))
+    }
+
+    fn read_body<R: Read>(&mut self, stream: &mut R, deadline: Instant) -> Result<(), HttpError> {
+        let length: usize = match self.header("content-length") {
+            Some(length) => length
+                .trim()
+                .parse()
+                .map_err(|_| HttpError::Malformed(format!("bad content length {:?}", length)))?,
+            None => return Ok(()),
+        };
+        if length > MAX_BODY_SIZE {
+            return Err(HttpError::TooLarge(length));
+        }
+        let mut chunk = [0; 4096];
+        while self.body.len() < length {
+            let wanted = (length - self.body.len()).min(chunk.len());
+            let read = read_before(stream, &mut chunk[..wanted], deadline)?;
+            if read == 0 {
+                return Err(HttpError::Malformed(String::from("body cut short")));
+            }
+            self.body.extend_from_slice(&chunk[..read]);
+        }
+        self.body.truncate(length);
+        Ok(())
+    }
+}
+
+// A single read can still take as long as the stream's own read timeout,
+// the deadline is checked between reads
+fn read_before<R: Read>(
+    stream: &mut R,
+    buffer: &mut [u8],
+    deadline: Instant,
+) -> Result<usize, HttpError> {
+    loop {
+        if Instant::now() >= deadline {
+            return Err(HttpError::TimedOut);
+        }
+        match stream.read(buffer) {
+            Ok(read) => return Ok(read),
+            Err(e) => match e.kind() {
+                io::ErrorKind::Interrupted => (),
+                // A timed out socket read gives either, depending on the platform
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    return Err(HttpError::TimedOut)
+                }
+                _ => return Err(HttpError::Io(e)),
+            },
+        }
+    }
+}
+
+// `status` is the status line without the version, e.g. "404 Not Found"
+pub fn respond<W: Write>(stream: &mut W, status: &str, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    if let Err(e) = stream
+        .write_all(response.as_bytes())
+        .and_then(|()| stream.flush())
+    {
+        log::warn!("Unable to send response: {}", e);
+    }
+}
+
+// Errors from JSON endpoints are JSON too
+pub fn respond_error<W: Write>(stream: &mut W, status: &str, message: &str) {
+    let body = serde_json::json!({ "error": message }).to_string();
+    respond(stream, status, "application/json", &body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request_with_body() {
+        let raw = b"POST /worlds/lab?token=abc HTTP/1.1\r\nHost: x\r\nContent-Length: 11\r\n\r\n{\"width\":5}";
+        let request = HttpRequest::read(&mut &raw[..]).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.segments(), vec!["worlds", "lab"]);
+        assert_eq!(request.token(), Some("abc"));
+        assert_eq!(request.body, b"{\"width\":5}");
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let raw = b"DELETE /worlds/lab HTTP/1.1\r\nAuthorization: Bearer xyz\r\n\r\n";
+        let request = HttpRequest::read(&mut &raw[..]).unwrap();
+
+        assert_eq!(request.token(), Some("xyz"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn test_stalled_request_times_out() {
+        // Sends the start of a request, then times out like a quiet socket would
+        struct Stalled<'a>(&'a [u8]);
+
+        impl Read for Stalled<'_> {
+            fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::from(io::ErrorKind::WouldBlock));
+                }
+                self.0.read(buffer)
+            }
+        }
+
+        let raw = b"POST /worlds/lab HTTP/1.1\r\nContent-Length: 11\r\n\r\n{\"wid";
+        let error = HttpRequest::read(&mut Stalled(raw)).err().unwrap();
+
+        assert!(matches!(error, HttpError::TimedOut));
+        assert_eq!(error.status(), "408 Request Timeout");
+    }
+}
@@ -1,208 +1,592 @@
-use std::env;
+use std::collections::BTreeMap;
 use std::io::prelude::*;
-use std::io::Write;
+use std::io::{self, Write};
 use std::net::{TcpListener, TcpStream};
+use std::process;
 use std::str;
 use std::sync::Arc;
-use std::sync::RwLock;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use rand_core::SeedableRng;
-
-use log;
-use pretty_env_logger;
-use tungstenite::protocol::Message;
-use tungstenite::server::accept;
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::http::HeaderValue;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::frame::CloseFrame;
+use tungstenite::protocol::{Message, WebSocket};
+use tungstenite::server::accept_hdr;
 
 use askama::Template;
 
-mod thread_pool;
-pub mod world;
-
-const TICK_RATE_MS: u64 = 100;
+use auth::Role;
+use commands::{Command, Notification, Reply};
+pub use config::Config;
+use event_bus::EventCursor;
+use history::Input;
+use http::HttpRequest;
+use metrics::{SessionMetrics, TickMetrics};
+use rate_limit::RateLimiter;
+use registry::{RegistryError, WorldRegistry, DEFAULT_WORLD};
+use shutdown::Shutdown;
+use simulation::{ConfiguredWorld, SharedWorld, Until};
+use thread_pool::PoolMonitor;
+use world::scenario::{EntitySpec, Scenario};
 
-pub struct Config {
-    pub host_address: String,
-}
-
-impl Config {
-    pub fn new() -> Config {
-        // This could be a value passed to the compiler
-        let host_address = env::var("HOST_ADDRESS").unwrap_or_else(|_| String::from("localhost"));
-        Config { host_address }
-    }
-}
+pub mod auth;
+pub mod commands;
+pub mod config;
+#[cfg(feature = "console-renderer")]
+pub mod console_renderer;
+pub mod encoding;
+pub mod entity_api;
+pub mod event_bus;
+pub mod headless;
+pub mod history;
+pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod rate_limit;
+pub mod registry;
+pub mod scheduler;
+mod sessions;
+pub mod shutdown;
+pub mod simulation;
+pub mod stats;
+pub mod sweep;
+pub mod thread_pool;
+pub mod world;
 
-pub struct ConfiguredWorld {
-    world: world::World,
-    tick_rate: u64,
-    randomizer: rand_pcg::Pcg32,
-}
+// How often the listener checks for a shutdown between connections
+const ACCEPT_POLL_INTERVAL_MS: u64 = 50;
+// How long a new connection gets to send the start of its request
+const PEEK_TIMEOUT_MS: u64 = 500;
+// How long any later read waits on the client before giving up on it
+const READ_TIMEOUT_MS: u64 = 5000;
 
 pub fn run(config: Config) {
-    pretty_env_logger::init();
+    let levels = config
+        .log_level
+        .parse()
+        .expect("Log levels are checked when the config is loaded");
+    logging::Logger::init(config.log_format, levels);
+    log::info!("Running with host address: {}", config.host_address);
 
-    let world = world::World::default();
-    let configured_world = ConfiguredWorld {
-        world: world,
-        tick_rate: TICK_RATE_MS,
-        randomizer: rand_pcg::Pcg32::from_seed(*b"somebody once to"),
+    let shutdown = Shutdown::new();
+    shutdown.install_signal_handler();
+
+    let world = match &config.scenario {
+        Some(path) => match Scenario::load(path) {
+            Ok(scenario) => scenario.build_world(),
+            Err(e) => {
+                log::error!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => world::World::default(),
     };
-    let world_ref_counter = Arc::new(RwLock::new(configured_world));
-    let primary_world_instance = Arc::clone(&world_ref_counter);
-    thread::spawn(move || {
-        let mut randomizer = rand_pcg::Pcg32::from_seed(*b"somebody once to");
-        let mut start;
-        let mut frame_time;
-        let mut lock_time;
-        loop {
-            start = Instant::now();
-
-            // A possible optimization: Have world calculate its value without
-            // getting a lock. Only lock when updating the value. Would need to
-            // test how often the lock is preventing reads.
-
-            // This scope is created to ensure the lock is dropped ASAP
-            {
-                let mut w = primary_world_instance.write().unwrap();
-                lock_time = start.elapsed().as_millis();
-                w.world.update_if_active(&mut randomizer);
+    let registry = Arc::new(WorldRegistry::new(&config, &shutdown));
+    let default_world = registry
+        .create(DEFAULT_WORLD, world)
+        .expect("registry starts out empty");
+
+    #[cfg(feature = "console-renderer")]
+    let console = {
+        let world_ref = Arc::clone(&default_world.shared);
+        let shutdown = shutdown.clone();
+        thread::spawn(move || {
+            if let Err(e) = console_renderer::run(&world_ref, &shutdown) {
+                log::error!("Console renderer failed: {}", e);
             }
-            frame_time = start.elapsed().as_millis() as u64;
+        })
+    };
 
-            log::info!(
-                "Frame processing took: {} (time to acquire lock: {})",
-                frame_time,
-                lock_time
-            );
-            {
-                let w = primary_world_instance.read().unwrap();
-                if frame_time > w.tick_rate {
-                    log::warn!(
-                        "WARNING: Frame processing ({}) took longer than tick rate ({})",
-                        frame_time,
-                        w.tick_rate
-                    );
-                    frame_time = w.tick_rate; // Prevent subtraction below from going negative
-                }
-                thread::sleep(Duration::from_millis(w.tick_rate - frame_time));
+    let event_log = config.event_log.clone().map(|path| {
+        let world_ref = Arc::clone(&default_world.shared);
+        let shutdown = shutdown.clone();
+        thread::spawn(move || {
+            if let Err(e) = event_bus::write_log(&world_ref.events, &path, &shutdown) {
+                log::error!("{}: {}", path.display(), e);
             }
-        }
+        })
     });
 
-    start_tcp_server(&world_ref_counter, config);
+    let snapshot_path = config.snapshot.clone();
+    start_tcp_server(&registry, config, &shutdown);
+
+    #[cfg(feature = "console-renderer")]
+    let _ = console.join();
+    registry.join_all();
+    if let Some(event_log) = event_log {
+        let _ = event_log.join();
+    }
+    // Other worlds are experiments and go away with the server
+    let snapshot = default_world.shared.current().world.snapshot();
+    match snapshot.save(&snapshot_path) {
+        Ok(()) => log::info!("Saved world snapshot to {}", snapshot_path.display()),
+        Err(e) => log::error!("{}: {}", snapshot_path.display(), e),
+    }
+    log::info!("Server stopped");
 }
 
-pub fn start_tcp_server(world_ref_counter: &Arc<RwLock<ConfiguredWorld>>, config: Config) {
+// Runs until a shutdown is requested, then waits for in-flight requests and
+// websocket sessions to finish
+pub fn start_tcp_server(registry: &Arc<WorldRegistry>, config: Config, shutdown: &Shutdown) {
     log::info!("Server started");
-    let listener = TcpListener::bind("0.0.0.0:7878").unwrap();
-    let pool = thread_pool::ThreadPool::new(4);
+    let listener = TcpListener::bind(config.listen_address()).unwrap();
+    // Non-blocking so the loop below can notice a shutdown while idle
+    listener.set_nonblocking(true).unwrap();
+    let pool = thread_pool::ThreadPool::new(config.workers);
+    // Read when /metrics is rendered, not when its request is queued
+    let request_pool = pool.monitor();
+    let mut sessions = sessions::SessionPool::new(config.max_connections);
 
-    let host_address = Arc::new(config.host_address);
+    if config.admin_token.is_none() {
+        log::warn!("ADMIN_TOKEN is not set, the world cannot be controlled from the browser");
+    }
+    let config = Arc::new(config);
+    // Tells apart the log lines of different connections
+    let mut next_connection_id: u64 = 0;
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
+    while !shutdown.is_requested() {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(ACCEPT_POLL_INTERVAL_MS));
+                continue;
+            }
+            Err(e) => {
+                log::error!("Unable to accept connection: {}", e);
+                continue;
+            }
+        };
+        let connection_id = next_connection_id;
+        next_connection_id += 1;
 
         let mut buffer = [0; 512]; // Dynamically size; will overflow as world size grows
-        stream.peek(&mut buffer).unwrap();
+        if let Err(e) = peek_request(&stream, &mut buffer) {
+            log::warn!("Dropped connection before reading its request: {}", e);
+            continue;
+        }
 
-        let world_ref = Arc::clone(&world_ref_counter);
-        let address_ref = Arc::clone(&host_address);
+        let registry_ref = Arc::clone(registry);
+        let config_ref = Arc::clone(&config);
+        let request_pool_ref = request_pool.clone();
+
+        let websocket = b"GET /websocket";
+        let world_websocket = b"GET /worlds/";
+        let websocket_world = if buffer.starts_with(websocket) {
+            Some(String::from(DEFAULT_WORLD))
+        } else if buffer.starts_with(world_websocket) {
+            websocket_world_name(&buffer)
+        } else {
+            None
+        };
+        if let Some(name) = websocket_world {
+            let running = match registry.get(&name) {
+                Some(running) => running,
+                // Answered from the pool, the listener never writes to a client
+                None => {
+                    pool.execute(move || handle_404(&stream));
+                    continue;
+                }
+            };
+            let mut rejected_stream = match stream.try_clone() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Unable to set up websocket connection: {}", e);
+                    continue;
+                }
+            };
+            let accepted = sessions.spawn(move || {
+                let _connection = logging::field("connection_id", connection_id);
+                let _world = logging::field("world", &running.name);
+                handle_websocket(&stream, &config_ref, &running.shared, &running.stop)
+            });
+            if !accepted {
+                log::warn!(
+                    "Rejected websocket, already at {} connections",
+                    config.max_connections
+                );
+                handle_unavailable(&mut rejected_stream);
+            }
+            continue;
+        }
 
         pool.execute(move || {
+            let _connection = logging::field("connection_id", connection_id);
             let index = b"GET / HTTP/1.1\r\n";
-            let debug_index = b"GET /?debug=9933212 HTTP/1.1\r\n";
+            let debug_index = b"GET /?token=";
             let world_status = b"GET /world_status HTTP/1.1\r\n";
-            let websocket = b"GET /websocket";
+            let metrics = b"GET /metrics HTTP/1.1\r\n";
+            let stats = b"GET /stats";
+            let healthz = b"GET /healthz";
+            let readyz = b"GET /readyz";
+            let worlds = [
+                &b"GET /worlds"[..],
+                b"POST /worlds",
+                b"PATCH /worlds",
+                b"DELETE /worlds",
+            ];
 
+            // The default world is created before the server starts and can't be destroyed
+            let default_world = registry_ref.get(DEFAULT_WORLD).unwrap();
             if buffer.starts_with(index) {
-                handle_index(&stream, &address_ref, &world_ref)
+                handle_index(&stream, &config_ref, &default_world.shared)
             } else if buffer.starts_with(debug_index) {
-                handle_debug_index(&stream, &address_ref, &world_ref)
+                handle_debug_index(&stream, &buffer, &config_ref, &default_world.shared)
             } else if buffer.starts_with(world_status) {
-                handle_world_status(&stream, &world_ref)
-            } else if buffer.starts_with(websocket) {
-                handle_websocket(&stream, &world_ref)
+                handle_world_status(&stream, &default_world.shared)
+            } else if buffer.starts_with(metrics) {
+                handle_metrics(&stream, &registry_ref, &request_pool_ref)
+            } else if buffer.starts_with(healthz) {
+                handle_healthz(&stream)
+            } else if buffer.starts_with(readyz) {
+                handle_readyz(&stream, &registry_ref)
+            } else if buffer.starts_with(stats) {
+                handle_stats(&stream, &default_world.shared)
+            } else if worlds.iter().any(|route| buffer.starts_with(route)) {
+                handle_worlds(&stream, &config_ref, &registry_ref)
             } else {
                 handle_404(&stream)
             };
         });
     }
+    log::info!("Stopped accepting connections, waiting for open sessions to close");
+}
+
+// Waits briefly for the start of the request, so a client that connects and
+// sends nothing can't hold up the listener. Reads stay timed out after that,
+// so a client that stops halfway can't hold up a worker either.
+fn peek_request(stream: &TcpStream, buffer: &mut [u8]) -> io::Result<()> {
+    // Some platforms hand out sockets that inherit the listener's mode
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_millis(PEEK_TIMEOUT_MS)))?;
+    stream.peek(buffer)?;
+    stream.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)))
+}
+
+// `GET /worlds/{name}/websocket` gives `name`
+fn websocket_world_name(request_buffer: &[u8]) -> Option<String> {
+    let path = request_path(request_buffer)?;
+    let name = path.strip_prefix("/worlds/")?.strip_suffix("/websocket")?;
+    Some(String::from(name))
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate<'a> {
-    host_address: &'a str,
+    websocket_url: &'a str,
     width: i32,
     height: i32,
     debug: bool,
+    palette: &'a [&'a str],
+    binary_subprotocol: &'a str,
+    token: &'a str,
+    // Where the world's stats and entities are served
+    world_url: &'a str,
 }
 
 const HTTP_OK: &str = "HTTP/1.1 200 OK\r\n\r\n";
 const HTTP_SERVER_ERROR: &str = "HTTP/1.1 200 OK\r\n\r\n";
+const HTTP_FORBIDDEN: &str = "HTTP/1.1 403 Forbidden\r\n\r\n";
+const HTTP_UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\n\r\n";
 
-fn handle_index(
-    mut stream: &TcpStream,
-    address_ref: &str,
-    world_ref: &Arc<RwLock<ConfiguredWorld>>,
-) {
-    // SECURITY: Even with debug = false, the ws could send arbitrary data
-    // This is decidedly unsecure but better than nothing
-    let w = &world_ref.read().unwrap();
-    let content = IndexTemplate {
-        host_address: &address_ref,
-        height: w.world.height,
-        width: w.world.width,
-        debug: false,
-    };
-    let response = format!("{}{}", HTTP_OK, content);
+fn handle_index(mut stream: &TcpStream, config: &Config, world_ref: &Arc<SharedWorld>) {
+    let response = format!(
+        "{}{}",
+        HTTP_OK,
+        render_index(world_ref, DEFAULT_WORLD, &config.websocket_url(), None)
+    );
 
-    stream.read(&mut [0; 512]).unwrap(); // Ensure stream is empty before writing
-    stream.write(response.as_bytes()).unwrap();
+    let _ = stream.read(&mut [0; 512]).unwrap(); // Ensure stream is empty before writing
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
 }
 
 fn handle_debug_index(
     mut stream: &TcpStream,
-    address_ref: &str,
-    world_ref: &Arc<RwLock<ConfiguredWorld>>,
+    request_buffer: &[u8],
+    config: &Config,
+    world_ref: &Arc<SharedWorld>,
 ) {
-    // SECURITY: Even with debug = false, the ws could send arbitrary data
-    // This is decidedly unsecure but better than nothing
-    let w = &world_ref.read().unwrap();
+    let query = request_query(request_buffer);
+    let offered_token = auth::token_from_query(query.as_deref());
+    let response = match Role::authorize(config.admin_token.as_deref(), offered_token) {
+        Role::Admin => format!(
+            "{}{}",
+            HTTP_OK,
+            render_index(
+                world_ref,
+                DEFAULT_WORLD,
+                &config.websocket_url(),
+                offered_token
+            )
+        ),
+        Role::Spectator => {
+            log::warn!("Rejected debug page request with invalid admin token");
+            String::from(HTTP_FORBIDDEN)
+        }
+    };
+
+    let _ = stream.read(&mut [0; 512]).unwrap(); // Ensure stream is empty before writing
+    stream.write_all(response.as_bytes()).unwrap();
+    stream.flush().unwrap();
+}
+
+// Passing the admin token renders the debug page, which hands the token back
+// on the websocket URL to open a control session
+fn render_index(
+    world_ref: &SharedWorld,
+    name: &str,
+    websocket_url: &str,
+    token: Option<&str>,
+) -> String {
+    let w = world_ref.current();
     let content = IndexTemplate {
-        host_address: &address_ref,
+        websocket_url,
         height: w.world.height,
         width: w.world.width,
-        debug: true,
+        debug: token.is_some(),
+        palette: &world::PALETTE,
+        binary_subprotocol: encoding::BINARY_SUBPROTOCOL,
+        token: token.unwrap_or_default(),
+        world_url: &format!("/worlds/{}", name),
     };
-    let response = format!("{}{}", HTTP_OK, content);
+    content.to_string()
+}
 
-    stream.read(&mut [0; 512]).unwrap(); // Ensure stream is empty before writing
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+// Only the request line is needed, so a partially read request is fine
+fn request_path(request_buffer: &[u8]) -> Option<String> {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut request = httparse::Request::new(&mut headers);
+    let _ = request.parse(request_buffer);
+    request.path.map(String::from)
 }
 
-fn handle_world_status(mut stream: &TcpStream, world_ref: &Arc<RwLock<ConfiguredWorld>>) {
-    let w = &world_ref.read().unwrap();
-    let rendered_entities = w.world.render();
-    let response;
-    match serde_json::to_string(&rendered_entities) {
-        Ok(serialized_player) => response = format!("{}{}", HTTP_OK, serialized_player),
+fn request_query(request_buffer: &[u8]) -> Option<String> {
+    let path = request_path(request_buffer)?;
+    path.split_once('?').map(|(_, query)| String::from(query))
+}
+
+fn handle_world_status(mut stream: &TcpStream, world_ref: &Arc<SharedWorld>) {
+    let rendered_entities = world_ref.current().world.render();
+    let response = match serde_json::to_string(&rendered_entities) {
+        Ok(serialized_player) => format!("{}{}", HTTP_OK, serialized_player),
         Err(e) => {
             log::error!("Unable to serialize player: {}", e);
-            response = String::from(HTTP_SERVER_ERROR);
+            String::from(HTTP_SERVER_ERROR)
+        }
+    };
+
+    // ensure stream is empty before writing
+    let _ = stream.read(&mut [0; 512]).unwrap();
+    stream.write_all(response.as_bytes()).unwrap();
+    stream.flush().unwrap();
+}
+
+fn handle_stats(mut stream: &TcpStream, world_ref: &SharedWorld) {
+    match HttpRequest::read(&mut stream) {
+        Ok(request) if request.segments() == ["stats"] => {
+            respond_stats(stream, &request, world_ref)
+        }
+        Ok(_) => handle_404(stream),
+        Err(e) => http::respond_error(&mut stream, e.status(), &e.to_string()),
+    }
+}
+
+// Population stats for the ticks still kept, oldest first. `?since=<tick>`
+// leaves out that tick and the ones before it.
+fn respond_stats(mut stream: &TcpStream, request: &HttpRequest, world_ref: &SharedWorld) {
+    let since = match request.query_param("since").map(str::parse::<u64>) {
+        Some(Ok(tick)) => Some(tick),
+        Some(Err(_)) => {
+            let message = "since must be a tick number";
+            return http::respond_error(&mut stream, "400 Bad Request", message);
         }
+        None => None,
     };
+    let stats = world_ref.inspect(|w| w.population.since(since));
+    let body = serde_json::to_string(&stats).unwrap();
+    http::respond(&mut stream, "200 OK", "application/json", &body)
+}
+
+fn handle_metrics(mut stream: &TcpStream, registry: &WorldRegistry, request_pool: &PoolMonitor) {
+    let mut response =
+        String::from("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\r\n");
+    let worlds = registry.all();
+    let tick_metrics: Vec<(&str, &TickMetrics)> = worlds
+        .iter()
+        .map(|running| (running.name.as_str(), &running.shared.metrics))
+        .collect();
+    metrics::render_tick_metrics(&mut response, &tick_metrics);
+    let session_metrics: Vec<(&str, &SessionMetrics)> = worlds
+        .iter()
+        .map(|running| (running.name.as_str(), &running.shared.sessions))
+        .collect();
+    metrics::render_session_metrics(&mut response, &session_metrics);
+    let entity_counts: Vec<(&str, BTreeMap<String, usize>)> = worlds
+        .iter()
+        .map(|running| {
+            let counts = running.shared.current().world.population().counts;
+            (running.name.as_str(), counts)
+        })
+        .collect();
+    metrics::render_entity_counts(&mut response, &entity_counts);
+    let pools = [
+        ("requests", request_pool.stats()),
+        ("simulation", registry.simulation_pool_stats()),
+    ];
+    metrics::render_pool_metrics(&mut response, &pools);
 
     // ensure stream is empty before writing
-    stream.read(&mut [0; 512]).unwrap();
-    stream.write(response.as_bytes()).unwrap();
+    let _ = stream.read(&mut [0; 512]).unwrap();
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
 }
 
+// Answering at all is the check, the process is alive
+fn handle_healthz(mut stream: &TcpStream) {
+    // ensure stream is empty before writing
+    let _ = stream.read(&mut [0; 512]);
+    let body = r#"{"status":"ok"}"#;
+    http::respond(&mut stream, "200 OK", "application/json", body)
+}
+
+// Ready while every world's tick thread is keeping up
+fn handle_readyz(mut stream: &TcpStream, registry: &WorldRegistry) {
+    let now = Instant::now();
+    let worlds: BTreeMap<String, simulation::Readiness> = registry
+        .all()
+        .iter()
+        .map(|running| (running.name.clone(), running.shared.readiness(now)))
+        .collect();
+    let ready = worlds.values().all(|world| world.ready);
+    for (name, world) in worlds.iter().filter(|(_, world)| !world.ready) {
+        log::warn!("World {} is not ready: {:?}", name, world.problem);
+    }
+    let body = serde_json::json!({ "ready": ready, "worlds": worlds }).to_string();
+    let status = if ready {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    };
+    // ensure stream is empty before writing
+    let _ = stream.read(&mut [0; 512]);
+    http::respond(&mut stream, status, "application/json", &body)
+}
+
+// `GET /worlds` lists worlds, `GET /worlds/{name}` serves a world's page
+// (the debug page with an admin token), and admins can `POST` a scenario to
+// `/worlds/{name}` to create a world or `DELETE` it to destroy it. A world's
+// entities are under `/worlds/{name}/entities`.
+fn handle_worlds(mut stream: &TcpStream, config: &Config, registry: &WorldRegistry) {
+    let request = match HttpRequest::read(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            log::warn!("{}", e);
+            return http::respond_error(&mut stream, e.status(), &e.to_string());
+        }
+    };
+    let role = Role::authorize(config.admin_token.as_deref(), request.token());
+    let name = match request.segments()[..] {
+        ["worlds"] if request.method == "GET" => {
+            let body = serde_json::to_string(&registry.list()).unwrap();
+            return http::respond(&mut stream, "200 OK", "application/json", &body);
+        }
+        ["worlds", name] => String::from(name),
+        ["worlds", name, "stats"] if request.method == "GET" => {
+            return match registry.get(name) {
+                Some(running) => respond_stats(stream, &request, &running.shared),
+                None => handle_404(stream),
+            };
+        }
+        ["worlds", name, "entities"] | ["worlds", name, "entities", _] => {
+            let id = request.segments().get(3).copied();
+            return match registry.get(name) {
+                Some(running) => {
+                    entity_api::handle(&mut stream, &request, role, &running.shared, id)
+                }
+                None => handle_404(stream),
+            };
+        }
+        _ => return handle_404(stream),
+    };
+
+    let result = match request.method.as_str() {
+        "GET" => {
+            let running = match registry.get(&name) {
+                Some(running) => running,
+                None => return handle_404(stream),
+            };
+            let token = if role.is_admin() {
+                request.token()
+            } else {
+                None
+            };
+            let websocket_url = config.world_websocket_url(&name);
+            let page = render_index(&running.shared, &name, &websocket_url, token);
+            return http::respond(&mut stream, "200 OK", "text/html", &page);
+        }
+        _ if !role.is_admin() => {
+            log::warn!(
+                "Rejected {} {} without an admin token",
+                request.method,
+                request.path
+            );
+            let message = "Creating and destroying worlds requires an admin token";
+            return http::respond_error(&mut stream, "403 Forbidden", message);
+        }
+        "POST" => create_world(registry, &name, &request.body),
+        "DELETE" => registry
+            .destroy(&name)
+            .map(|()| "204 No Content")
+            .map_err(|e| (registry_error_status(&e), e.to_string())),
+        _ => Err((
+            "405 Method Not Allowed",
+            format!("Unsupported method {}", request.method),
+        )),
+    };
+    match result {
+        // Created worlds are described in the response, destroyed ones aren't
+        Ok(status) => {
+            let summary = registry.list().into_iter().find(|world| world.name == name);
+            let body = summary.map_or_else(String::new, |summary| {
+                serde_json::to_string(&summary).unwrap()
+            });
+            http::respond(&mut stream, status, "application/json", &body)
+        }
+        Err((status, message)) => {
+            log::warn!("{} {}: {}", request.method, request.path, message);
+            http::respond_error(&mut stream, status, &message)
+        }
+    }
+}
+
+// An empty body creates a world from the default scenario
+fn create_world(
+    registry: &WorldRegistry,
+    name: &str,
+    body: &[u8],
+) -> Result<&'static str, (&'static str, String)> {
+    let scenario = match str::from_utf8(body) {
+        Ok(json) if json.trim().is_empty() => Scenario::default(),
+        Ok(json) => Scenario::from_json(json).map_err(|e| ("400 Bad Request", e.to_string()))?,
+        Err(e) => return Err(("400 Bad Request", e.to_string())),
+    };
+    registry
+        .create(name, scenario.build_world())
+        .map(|_| "201 Created")
+        .map_err(|e| (registry_error_status(&e), e.to_string()))
+}
+
+fn registry_error_status(error: &RegistryError) -> &'static str {
+    match error {
+        RegistryError::InvalidName(_) => "400 Bad Request",
+        RegistryError::AlreadyExists(_) => "409 Conflict",
+        RegistryError::NotFound(_) => "404 Not Found",
+        RegistryError::Protected(_) => "403 Forbidden",
+    }
+}
+
 #[derive(Template)]
 #[template(path = "404.html")]
 struct NotFoundTemplate {}
@@ -214,17 +598,68 @@ fn handle_404(mut stream: &TcpStream) {
     let response = format!("{}{}", status_line, contents);
     // ensure stream is empty before writing
     let mut buffer = [0; 512]; // Dynamically size; will overflow as world size grows
-    stream.read(&mut buffer).unwrap();
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+    let _ = stream.read(&mut buffer);
+    if let Err(e) = stream
+        .write_all(response.as_bytes())
+        .and_then(|()| stream.flush())
+    {
+        log::warn!("Unable to send response: {}", e);
+    }
 }
 
-fn handle_websocket(stream: &TcpStream, world_ref: &Arc<RwLock<ConfiguredWorld>>) {
-    let mut websocket = accept(stream).unwrap();
+fn handle_unavailable(stream: &mut TcpStream) {
+    // The request is left unread; the connection is closed right after anyway
+    if let Err(e) = stream.write_all(HTTP_UNAVAILABLE.as_bytes()) {
+        log::warn!("Unable to reject connection: {}", e);
+    }
+}
+
+fn handle_websocket(
+    stream: &TcpStream,
+    config: &Config,
+    world_ref: &Arc<SharedWorld>,
+    shutdown: &Shutdown,
+) {
+    let mut encoding = encoding::Encoding::Json;
+    let mut role = Role::Spectator;
+    // The error type is fixed by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let negotiate_session = |request: &Request, mut response: Response| {
+        encoding = encoding::Encoding::negotiate(request);
+        let offered_token = auth::token_from_query(request.uri().query());
+        role = Role::authorize(config.admin_token.as_deref(), offered_token);
+        if encoding::offers_binary_subprotocol(request) {
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(encoding::BINARY_SUBPROTOCOL),
+            );
+        }
+        Ok(response)
+    };
+    let mut websocket = match accept_hdr(stream, negotiate_session) {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            log::warn!("Websocket handshake failed: {}", e);
+            return;
+        }
+    };
+    log::info!("Websocket session opened as {:?}", role);
+    // Only stops that happen while the session is open get reported
+    let mut reported_stop = world_ref.current().last_stop.map(|stop| stop.id);
+    // Set while the session is looking at a past tick
+    let mut view: Option<world::World> = None;
+    let mut food_limiter = RateLimiter::new(Duration::from_millis(config.food_cooldown_ms));
+    let mut subscriptions = Subscriptions::default();
+    // Tick of the last stats sent, each tick's stats are sent once
+    let mut sent_stats_tick = None;
+    let _connected = world_ref.sessions.connect();
     websocket.get_mut().set_nodelay(true).unwrap(); // Disables Nagle's Algorithm, reduces stream delays
     websocket.get_mut().set_nonblocking(true).unwrap();
-    let mut tick_rate;
     loop {
+        if shutdown.is_requested() {
+            close_websocket(&mut websocket);
+            return;
+        }
         match websocket.read_message() {
             Ok(msg) => match msg {
                 Message::Close(_) => {
@@ -242,7 +677,19 @@ fn handle_websocket(stream: &TcpStream, world_ref: &Arc<RwLock<ConfiguredWorld>>
                     return;
                 }
                 Message::Text(msg_string) => {
-                    handle_ws_text_msg(&msg_string[..], world_ref);
+                    let reply = handle_ws_text_msg(
+                        &msg_string[..],
+                        role,
+                        world_ref,
+                        &mut view,
+                        &mut food_limiter,
+                        &mut subscriptions,
+                    );
+                    let reply = Message::text(reply.to_message());
+                    if let Err(e) = send(&mut websocket, reply, &world_ref.sessions) {
+                        log::warn!("Unable to send websocket reply: {}", e);
+                        return;
+                    }
                 }
                 _ => log::error!("Unexpected type of websocket message: {}", msg),
             },
@@ -259,52 +706,216 @@ fn handle_websocket(stream: &TcpStream, world_ref: &Arc<RwLock<ConfiguredWorld>>
                 }
             }
         };
-        let result;
-        let rendered_entities;
-        // Scope reduces time the world lock is held
-        {
-            let w = world_ref.read().unwrap();
-            rendered_entities = w.world.render();
-            tick_rate = w.tick_rate;
+        let frame = world_ref.current();
+        let last_stop = frame.last_stop.map(|stop| stop.id);
+        if role.is_admin() && last_stop != reported_stop {
+            reported_stop = last_stop;
+            if let Some(stop) = frame.last_stop {
+                let notification = Message::text(Notification::Stopped(stop).to_message());
+                if let Err(e) = send(&mut websocket, notification, &world_ref.sessions) {
+                    log::warn!("Unable to send websocket notification: {}", e);
+                    return;
+                }
+            }
+        }
+        let tick = frame.world.current_tick();
+        if subscriptions.stats && sent_stats_tick != Some(tick) {
+            sent_stats_tick = Some(tick);
+            let stats = Message::text(Notification::Stats(frame.world.population()).to_message());
+            if let Err(e) = send(&mut websocket, stats, &world_ref.sessions) {
+                log::warn!("Unable to send population stats: {}", e);
+                return;
+            }
         }
+        if let Some(cursor) = subscriptions.events.as_mut() {
+            let received = world_ref.events.read(cursor);
+            let missed = Some(received.missed)
+                .filter(|ticks| *ticks > 0)
+                .map(|ticks| Notification::EventsMissed { ticks });
+            let events = received.ticks.into_iter().map(|tick| Notification::Events {
+                tick: tick.tick,
+                events: tick.events.to_vec(),
+            });
+            for notification in missed.into_iter().chain(events) {
+                let notification = Message::text(notification.to_message());
+                if let Err(e) = send(&mut websocket, notification, &world_ref.sessions) {
+                    log::warn!("Unable to send world events: {}", e);
+                    return;
+                }
+            }
+        }
+        let render = logging::span("render");
+        let render_start = Instant::now();
+        let rendered_entities = view.as_ref().unwrap_or(&frame.world).render();
+        let render_time = render_start.elapsed();
+        let tick_rate = frame.tick_rate;
         // TODO: Re-rendering the entites for every open websocket is unecessary
-        match serde_json::to_string(&rendered_entities) {
-            Ok(serialized_player) => result = format!("{}", serialized_player),
+        let response = match encoding.encode(&rendered_entities) {
+            Ok(message) => message,
             Err(e) => {
-                log::error!("Unable to serialize player: {}", e);
+                log::error!("Unable to encode frame: {}", e);
                 return;
             }
         };
-        let response = Message::text(result);
-        websocket.write_message(response).unwrap();
+        let serialize_time = render_start.elapsed() - render_time;
+        world_ref.sessions.record_frame(render_time, serialize_time);
+        drop(render);
+        let broadcast = logging::span("broadcast");
+        // A session thread outlives its client if it panics here, so give up quietly
+        if let Err(e) = send(&mut websocket, response, &world_ref.sessions) {
+            log::warn!("Unable to send world to websocket, closing session: {}", e);
+            return;
+        }
+        drop(broadcast);
 
         thread::sleep(Duration::from_millis(tick_rate));
     }
 }
 
-fn handle_ws_text_msg(msg_string: &str, world_ref: &Arc<RwLock<ConfiguredWorld>>) {
-    match msg_string {
-        "pause" => {
-            let mut w = world_ref.write().unwrap();
-            w.world.pause();
-        }
-        "unpause" => {
-            let mut w = world_ref.write().unwrap();
-            w.world.unpause();
-        }
-        "update" => {
-            let w = &mut *world_ref.write().unwrap();
-            w.world.update(&mut w.randomizer);
-        }
-        // For now, assume anything with a number is a tick rate change
-        tick_rate if tick_rate.chars().any(|c| c.is_numeric()) => {
-            let w = &mut *world_ref.write().unwrap();
-            let tick_rate_vector: Vec<u32> =
-                tick_rate.chars().filter_map(|c| c.to_digit(10)).collect();
-            let new_tick_rate = tick_rate_vector.iter().fold(0, |acc, elem| acc * 10 + elem);
-            w.tick_rate = new_tick_rate as u64;
-        }
-        _ => log::warn!("Unknown websocket text message: {}", msg_string),
+// Counted towards the world's bytes sent
+fn send(
+    websocket: &mut WebSocket<&TcpStream>,
+    message: Message,
+    metrics: &SessionMetrics,
+) -> tungstenite::Result<()> {
+    metrics.record_sent(message.len());
+    websocket.write_message(message)
+}
+
+// Sends a close frame and waits briefly for the client to acknowledge it
+fn close_websocket(websocket: &mut WebSocket<&TcpStream>) {
+    let close_frame = CloseFrame {
+        code: CloseCode::Away,
+        reason: "World is shutting down".into(),
+    };
+    if let Err(e) = websocket.close(Some(close_frame)) {
+        log::warn!("Unable to send websocket close frame: {}", e);
+        return;
+    }
+    for _ in 0..20 {
+        match websocket.read_message() {
+            Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(ACCEPT_POLL_INTERVAL_MS));
+            }
+            // Either the close handshake completed or the client went away
+            Err(_) => return,
+            Ok(_) => (),
+        }
+    }
+    log::warn!("Client did not acknowledge websocket close frame");
+}
+
+// What a websocket session has asked to be sent besides the world
+#[derive(Default)]
+struct Subscriptions {
+    stats: bool,
+    // Where the session is up to in the live world's events
+    events: Option<EventCursor>,
+}
+
+fn handle_ws_text_msg(
+    msg_string: &str,
+    role: Role,
+    world_ref: &Arc<SharedWorld>,
+    view: &mut Option<world::World>,
+    food_limiter: &mut RateLimiter,
+    subscriptions: &mut Subscriptions,
+) -> Reply {
+    let command = match Command::parse(msg_string) {
+        Ok(command) => command,
+        Err(e) => {
+            log::warn!("Rejected websocket command {:?}: {}", msg_string, e);
+            return Reply::Error {
+                message: e.to_string(),
+            };
+        }
+    };
+    if command.requires_admin() && !role.is_admin() {
+        log::warn!("Rejected control command from spectator: {:?}", command);
+        return Reply::Error {
+            message: String::from("Control commands require an admin session"),
+        };
+    }
+
+    let result = match command {
+        // Replaying happens outside the lock so the simulation isn't held up
+        Command::ViewTick { tick } => world_ref
+            .inspect(|w| w.replay_to(tick))
+            .map(|replay| *view = Some(replay.run().0)),
+        Command::ViewLive => {
+            *view = None;
+            Ok(())
+        }
+        Command::Fork { tick } => world_ref.update(|w| w.fork(tick)),
+        Command::PlaceFood { position } => place_food(world_ref, position, food_limiter),
+        Command::SubscribeStats | Command::UnsubscribeStats => {
+            subscriptions.stats = command == Command::SubscribeStats;
+            Ok(())
+        }
+        // Resubscribing keeps the session's place
+        Command::SubscribeEvents => {
+            if subscriptions.events.is_none() {
+                subscriptions.events = Some(world_ref.events.cursor());
+            }
+            Ok(())
+        }
+        Command::UnsubscribeEvents => {
+            subscriptions.events = None;
+            Ok(())
+        }
+        _ => {
+            world_ref.update(|w| control_world(w, &command));
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => Reply::Ack { command },
+        Err(message) => Reply::Error { message },
+    }
+}
+
+// Goes through the input queue like admin changes do, so placed food is
+// replayed along with everything else
+fn place_food(
+    world_ref: &SharedWorld,
+    position: world::Position,
+    food_limiter: &mut RateLimiter,
+) -> Result<(), String> {
+    let input = Input::Spawn {
+        entity: EntitySpec::Food { position },
+    };
+    world_ref.check_input(&input).map_err(|e| e.to_string())?;
+    if let Err(wait) = food_limiter.try_acquire(Instant::now()) {
+        return Err(format!(
+            "Placing food too quickly, try again in {}ms",
+            wait.as_millis()
+        ));
+    }
+    world_ref.update(|w| w.queue_input(input));
+    Ok(())
+}
+
+pub(crate) fn control_world(w: &mut ConfiguredWorld, command: &Command) {
+    match *command {
+        Command::Pause => w.pause(),
+        Command::Resume => w.resume(),
+        // Ticks run on the tick thread like any others, the client hears
+        // back once the last one has
+        Command::Step { n } => {
+            let tick = w.world.current_tick() + n as u64;
+            w.run_until(Until::Tick(tick));
+        }
+        Command::RunUntil { until } => w.run_until(until),
+        Command::SetTickRate { ms } => w.tick_rate = ms,
+        Command::SetSpeed { speed } => w.speed = speed,
+        Command::ViewTick { .. }
+        | Command::ViewLive
+        | Command::Fork { .. }
+        | Command::PlaceFood { .. }
+        | Command::SubscribeStats
+        | Command::UnsubscribeStats
+        | Command::SubscribeEvents
+        | Command::UnsubscribeEvents => (),
     }
 }
 
@@ -317,32 +928,213 @@ mod tests {
 
     fn get_mock_config() -> Config {
         Config {
-            host_address: String::from("localhost"),
+            admin_token: Some(String::from("secret")),
+            ..Config::default()
         }
     }
 
+    fn get_mock_world() -> ConfiguredWorld {
+        ConfiguredWorld::new(
+            world::World::default(),
+            get_mock_config().tick_rate_ms,
+            get_mock_config().randomizer(),
+            get_mock_config().history(),
+        )
+    }
+
     #[test]
     fn test_handle_index() {
+        let server =
+            TcpListener::bind("localhost:7880").expect("Can't listen, is port already used?");
         let _ = spawn(move || {
-            let server =
-                TcpListener::bind("localhost:7880").expect("Can't listen, is port already used?");
-            let world_ref_counter = Arc::new(RwLock::new(world::World::default()));
+            let world_ref_counter = Arc::new(SharedWorld::new(get_mock_world()));
             let stream = server.incoming().next().unwrap().unwrap();
             let mock_config = get_mock_config();
-            handle_index(&stream, &mock_config.host_address[..], &world_ref_counter);
+            handle_index(&stream, &mock_config, &world_ref_counter);
         });
 
         let mut client = TcpStream::connect("localhost:7880").expect("Can't connect to port");
-        client.write(b"/index").unwrap(); // Unblocks ".next()" in server. Ideally we could get a stream without this
+        client.write_all(b"/index").unwrap(); // Unblocks ".next()" in server. Ideally we could get a stream without this
 
         let mut buffer = [0; 2048];
-        client.read(&mut buffer).unwrap();
+        let _ = client.read(&mut buffer).unwrap();
         let response = String::from_utf8_lossy(&buffer);
 
         let expected_response = "<canvas id=\"game-canvas\"></canvas>";
         assert!(response.contains(expected_response));
     }
 
+    #[test]
+    fn test_spectator_cannot_control_world() {
+        let world_ref = Arc::new(SharedWorld::new(get_mock_world()));
+        let pause = r#"{"command": "pause"}"#;
+
+        let mut limiter = RateLimiter::new(Duration::from_millis(0));
+
+        let reply = handle_ws_text_msg(
+            pause,
+            Role::Spectator,
+            &world_ref,
+            &mut None,
+            &mut limiter,
+            &mut Subscriptions::default(),
+        );
+        assert!(matches!(reply, Reply::Error { .. }));
+
+        let reply = handle_ws_text_msg(
+            pause,
+            Role::Admin,
+            &world_ref,
+            &mut None,
+            &mut limiter,
+            &mut Subscriptions::default(),
+        );
+        assert_eq!(
+            reply,
+            Reply::Ack {
+                command: Command::Pause
+            }
+        );
+    }
+
+    #[test]
+    fn test_spectators_can_place_food() {
+        let world_ref = Arc::new(SharedWorld::new(get_mock_world()));
+        let mut limiter = RateLimiter::new(Duration::from_secs(60));
+        let place_food = |x, y| {
+            format!(
+                r#"{{"command": "place_food", "position": {{"x": {}, "y": {}}}}}"#,
+                x, y
+            )
+        };
+        let mut send = |msg: String| {
+            handle_ws_text_msg(
+                &msg,
+                Role::Spectator,
+                &world_ref,
+                &mut None,
+                &mut limiter,
+                &mut Subscriptions::default(),
+            )
+        };
+
+        // The default world has food at (20, 20)
+        assert!(matches!(send(place_food(20, 20)), Reply::Error { .. }));
+        assert!(matches!(send(place_food(99, 0)), Reply::Error { .. }));
+        assert!(matches!(send(place_food(3, 3)), Reply::Ack { .. }));
+        assert!(matches!(send(place_food(4, 4)), Reply::Error { .. }));
+
+        let entities = world_ref.current().world.entity_count();
+        world_ref.update(|w| w.tick(None));
+        assert_eq!(world_ref.current().world.entity_count(), entities + 1);
+    }
+
+    #[test]
+    fn test_websockets_do_not_block_requests() {
+        let config = Config {
+            bind_address: String::from("127.0.0.1"),
+            port: 7882,
+            workers: 1,
+            max_connections: 3,
+            ..get_mock_config()
+        };
+        let shutdown = Shutdown::new();
+        let registry = Arc::new(WorldRegistry::new(&config, &shutdown));
+        registry
+            .create(DEFAULT_WORLD, world::World::default())
+            .unwrap();
+        let server_registry = Arc::clone(&registry);
+        let server_shutdown = shutdown.clone();
+        let server = spawn(move || start_tcp_server(&server_registry, config, &server_shutdown));
+
+        let url = "ws://127.0.0.1:7882/websocket";
+        let mut clients = vec![];
+        for _ in 0..20 {
+            if let Ok((client, _)) = connect(url) {
+                clients.push(client);
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        for _ in 0..2 {
+            clients.push(connect(url).expect("Can't open websocket").0);
+        }
+        assert!(
+            connect(url).is_err(),
+            "connection over the limit was accepted"
+        );
+
+        // More websockets are open than there are workers, HTTP still works
+        let mut http_client = TcpStream::connect("127.0.0.1:7882").unwrap();
+        http_client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        http_client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("<canvas id=\"game-canvas\"></canvas>"));
+
+        drop(clients);
+        shutdown.request();
+        server.join().unwrap();
+        registry.join_all();
+    }
+
+    fn http_request(port: u16, request: &str) -> String {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let mut buffer = [0; 4096];
+        let read = client.read(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..read]).into_owned()
+    }
+
+    #[test]
+    fn test_create_and_destroy_worlds_over_http() {
+        let config = Config {
+            bind_address: String::from("127.0.0.1"),
+            port: 7883,
+            ..get_mock_config()
+        };
+        let shutdown = Shutdown::new();
+        let registry = Arc::new(WorldRegistry::new(&config, &shutdown));
+        registry
+            .create(DEFAULT_WORLD, world::World::default())
+            .unwrap();
+        let server_registry = Arc::clone(&registry);
+        let server_shutdown = shutdown.clone();
+        let server = spawn(move || start_tcp_server(&server_registry, config, &server_shutdown));
+        for _ in 0..20 {
+            if TcpStream::connect("127.0.0.1:7883").is_ok() {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+
+        let scenario = r#"{"width": 8, "height": 6, "entities": []}"#;
+        let create = format!(
+            "POST /worlds/lab HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            scenario.len(),
+            scenario
+        );
+        assert!(http_request(7883, &create).starts_with("HTTP/1.1 403"));
+        let create = create.replace("\r\n\r\n", "\r\nAuthorization: Bearer secret\r\n\r\n");
+        let response = http_request(7883, &create);
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert!(response.contains(r#""width":8"#));
+        assert!(http_request(7883, &create).starts_with("HTTP/1.1 409"));
+
+        let response = http_request(7883, "GET /worlds/lab HTTP/1.1\r\n\r\n");
+        assert!(response.contains("/worlds/lab/websocket"));
+        let response = http_request(7883, "GET /worlds HTTP/1.1\r\n\r\n");
+        assert!(response.contains(r#""name":"default""#));
+        assert!(response.contains(r#""name":"lab""#));
+
+        let destroy = "DELETE /worlds/lab?token=secret HTTP/1.1\r\n\r\n";
+        assert!(http_request(7883, destroy).starts_with("HTTP/1.1 204"));
+        assert!(registry.get("lab").is_none());
+
+        shutdown.request();
+        server.join().unwrap();
+        registry.join_all();
+    }
+
     // Websocket testing fn borrowed from:
     // https://github.com/snapview/tungstenite-rs/blob/master/tests/connection_reset.rs
     type Sock = WebSocket<Stream<TcpStream, TlsStream<TcpStream>>>;
@@ -368,21 +1160,20 @@ mod tests {
         // Setup world instance
         // ==============================
         // Warning: As world creation expands this will need to be mocked
-        let world_ref_counter = Arc::new(RwLock::new(world::World::default()));
+        let world_ref_counter = Arc::new(SharedWorld::new(get_mock_world()));
         let primary_world_instance = Arc::clone(&world_ref_counter);
         thread::spawn(move || {
-            let mut randomizer = rand_pcg::Pcg32::from_seed(*b"somebody once to");
+            let mut randomizer = get_mock_config().randomizer();
             loop {
-                thread::sleep(Duration::from_millis(TICK_RATE_MS));
-                let mut w = primary_world_instance.write().unwrap();
-                w.update(&mut randomizer);
+                thread::sleep(Duration::from_millis(get_mock_config().tick_rate_ms));
+                primary_world_instance.update(|w| w.world.update(&mut randomizer));
             }
         });
         let world_ref = Arc::clone(&world_ref_counter);
         // ===============================
 
         // Begin websocket handler
-        handle_websocket(&stream, &world_ref);
+        handle_websocket(&stream, &get_mock_config(), &world_ref, &Shutdown::new());
 
         client_thread.join().unwrap();
         println!("Done");
@@ -398,7 +1189,7 @@ mod tests {
             assert!(first_message.is_text());
             println!("  First message!");
 
-            thread::sleep(Duration::from_millis(TICK_RATE_MS));
+            thread::sleep(Duration::from_millis(get_mock_config().tick_rate_ms));
 
             let second_message = cli_sock.read_message().unwrap();
             assert!(second_message.is_text());
@@ -0,0 +1,293 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Target of the timings logged by `span`, so they can be turned on by
+// themselves with `garden::span=trace`
+pub const SPAN_TARGET: &str = "garden::span";
+
+thread_local! {
+    // Fields attached to everything the thread logs, see `field`
+    static FIELDS: RefCell<Vec<(&'static str, Value)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // `INFO  garden::registry > Created world lab world=lab`
+    Text,
+    // One object per line with `level`, `target`, `message`, `time_ms` and
+    // the fields
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<LogFormat, ()> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+// Levels as `info,garden::registry=debug,garden::span=trace`. A bare level
+// is the default, the rest apply to a target and everything under it, the
+// longest matching target winning. env_logger's `/regex` message filter, as
+// `RUST_LOG` may have one, is ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl FromStr for LogLevels {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<LogLevels, String> {
+        let mut levels = LogLevels {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        };
+        let spec = spec.split_once('/').map_or(spec, |(spec, _filter)| spec);
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse_level =
+                |level: &str| LevelFilter::from_str(level).map_err(|_| directive.to_string());
+            match directive.split_once('=') {
+                Some((target, level)) => levels
+                    .targets
+                    .push((target.to_string(), parse_level(level)?)),
+                None => levels.default = parse_level(directive)?,
+            }
+        }
+        // Longest first, so the first match is the most specific
+        levels
+            .targets
+            .sort_by_key(|(target, _)| Reverse(target.len()));
+        Ok(levels)
+    }
+}
+
+impl LogLevels {
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || (target.starts_with(prefix.as_str())
+                        && target[prefix.len()..].starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        let targets = self.targets.iter().map(|(_, level)| *level);
+        targets.fold(self.default, Ord::max)
+    }
+}
+
+pub struct Logger {
+    format: LogFormat,
+    levels: LogLevels,
+}
+
+impl Logger {
+    // Installs the logger for the whole process; only the first call takes
+    pub fn init(format: LogFormat, levels: LogLevels) {
+        log::set_max_level(levels.max());
+        let logger = Box::new(Logger { format, levels });
+        if log::set_boxed_logger(logger).is_err() {
+            log::warn!("A logger is already installed");
+        }
+    }
+
+    fn format(&self, record: &Record) -> String {
+        FIELDS.with(|fields| {
+            let fields = fields.borrow();
+            match self.format {
+                LogFormat::Text => format_text(record, &fields),
+                LogFormat::Json => format_json(record, &fields),
+            }
+        })
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        // Nowhere left to report a failure to log
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+fn format_text(record: &Record, fields: &[(&'static str, Value)]) -> String {
+    let mut line = format!(
+        "{:<5} {} > {}",
+        record.level(),
+        record.target(),
+        record.args()
+    );
+    for (key, value) in fields {
+        // Strings without their quotes
+        let value = match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    line
+}
+
+fn format_json(record: &Record, fields: &[(&'static str, Value)]) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut object = Map::new();
+    object.insert(String::from("time_ms"), (now.as_millis() as u64).into());
+    object.insert(String::from("level"), record.level().to_string().into());
+    object.insert(String::from("target"), record.target().into());
+    object.insert(String::from("message"), record.args().to_string().into());
+    for (key, value) in fields {
+        object.insert(key.to_string(), value.clone());
+    }
+    Value::Object(object).to_string()
+}
+
+// Removes its field when dropped
+pub struct FieldGuard {
+    depth: usize,
+}
+
+impl Drop for FieldGuard {
+    fn drop(&mut self) {
+        FIELDS.with(|fields| fields.borrow_mut().truncate(self.depth));
+    }
+}
+
+// Attaches `key` to everything this thread logs until the guard is dropped,
+// e.g. the connection a session thread serves or the tick being run
+pub fn field<T: Serialize>(key: &'static str, value: T) -> FieldGuard {
+    let value = serde_json::to_value(value).unwrap_or(Value::Null);
+    FIELDS.with(|fields| {
+        let mut fields = fields.borrow_mut();
+        let depth = fields.len();
+        fields.push((key, value));
+        FieldGuard { depth }
+    })
+}
+
+// The fields a thread has attached, to carry them over to work it hands to
+// another thread
+#[derive(Clone)]
+pub struct Fields(Vec<(&'static str, Value)>);
+
+pub fn current_fields() -> Fields {
+    FIELDS.with(|fields| Fields(fields.borrow().clone()))
+}
+
+impl Fields {
+    // Attaches them to everything this thread logs until the guard is dropped
+    pub fn attach(self) -> FieldGuard {
+        FIELDS.with(|fields| {
+            let mut fields = fields.borrow_mut();
+            let depth = fields.len();
+            fields.extend(self.0);
+            FieldGuard { depth }
+        })
+    }
+}
+
+// Logs how long a phase took when dropped, at trace level on SPAN_TARGET with
+// `span` and `duration_us` fields. Free when that's turned off.
+pub struct Span {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+pub fn span(name: &'static str) -> Span {
+    let enabled = log::log_enabled!(target: SPAN_TARGET, Level::Trace);
+    Span {
+        name,
+        start: if enabled { Some(Instant::now()) } else { None },
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let duration_us = start.elapsed().as_micros() as u64;
+            let _span = field("span", self.name);
+            let _duration = field("duration_us", duration_us);
+            log::trace!(target: SPAN_TARGET, "{} took {}us", self.name, duration_us);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_specific_level_wins() {
+        let levels: LogLevels = "warn, garden=info,garden::registry=trace".parse().unwrap();
+        assert_eq!(levels.level_for("garden::registry"), LevelFilter::Trace);
+        assert_eq!(
+            levels.level_for("garden::registry::tick"),
+            LevelFilter::Trace
+        );
+        assert_eq!(levels.level_for("garden::registryx"), LevelFilter::Info);
+        assert_eq!(levels.level_for("garden"), LevelFilter::Info);
+        assert_eq!(levels.level_for("tungstenite"), LevelFilter::Warn);
+        assert_eq!(levels.max(), LevelFilter::Trace);
+        assert!("garden=loud".parse::<LogLevels>().is_err());
+        let filtered: LogLevels = "warn,garden=debug/tick \\d+".parse().unwrap();
+        assert_eq!(filtered.level_for("garden"), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn test_json_lines_carry_fields() {
+        let _world = field("world", "lab");
+        let line = {
+            let _tick = field("tick", 7);
+            let record = Record::builder()
+                .level(Level::Warn)
+                .target("garden::registry")
+                .args(format_args!("Slow tick"))
+                .build();
+            FIELDS.with(|fields| format_json(&record, &fields.borrow()))
+        };
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "Slow tick");
+        assert_eq!(line["world"], "lab");
+        assert_eq!(line["tick"], 7);
+        // The tick's guard is gone, the world's isn't
+        FIELDS.with(|fields| assert_eq!(fields.borrow().len(), 1));
+
+        let fields = current_fields();
+        let carried = std::thread::spawn(move || {
+            let _fields = fields.attach();
+            FIELDS.with(|fields| fields.borrow().clone())
+        });
+        assert_eq!(carried.join().unwrap(), vec![("world", Value::from("lab"))]);
+    }
+}
@@ -1,7 +1,17 @@
+use std::env;
+use std::process;
 
-fn main()  {
-    let config = garden::Config::new();
-    println!("Running with host address: {}", config.host_address);
-
+fn main() {
+    let config = match garden::Config::load(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(garden::config::ConfigError::HelpRequested) => {
+            println!("{}", garden::config::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
     garden::run(config);
-}
\ No newline at end of file
+}
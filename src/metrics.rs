@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::thread_pool::PoolStats;
+
+// Upper bounds in seconds of the tick duration histogram's buckets
+const TICK_DURATION_BUCKETS: [f64; 10] =
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+// Timings of the tick thread, served in the Prometheus text format on /metrics
+#[derive(Default)]
+pub struct TickMetrics {
+    ticks: AtomicU64,
+    // Ticks that took at most each of TICK_DURATION_BUCKETS
+    tick_duration_buckets: [AtomicU64; TICK_DURATION_BUCKETS.len()],
+    last_frame_time_us: AtomicU64,
+    frame_time_us_total: AtomicU64,
+    last_lock_wait_us: AtomicU64,
+    lock_wait_us_total: AtomicU64,
+    // For measuring how long ago the last tick was
+    last_tick: Mutex<Option<Instant>>,
+    // Unix time in milliseconds, 0 before the first tick, only for reporting
+    last_tick_at_ms: AtomicU64,
+}
+
+impl TickMetrics {
+    pub fn record_tick(&self, frame_time: Duration, lock_wait: Duration) {
+        let frame_time = frame_time.as_micros() as u64;
+        let lock_wait = lock_wait.as_micros() as u64;
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        *self.last_tick.lock().unwrap() = Some(Instant::now());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_tick_at_ms
+            .store(now.as_millis() as u64, Ordering::Relaxed);
+        let buckets = TICK_DURATION_BUCKETS.iter();
+        for (bound, count) in buckets.zip(self.tick_duration_buckets.iter()) {
+            if frame_time as f64 / 1_000_000.0 <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.last_frame_time_us.store(frame_time, Ordering::Relaxed);
+        self.frame_time_us_total
+            .fetch_add(frame_time, Ordering::Relaxed);
+        self.last_lock_wait_us.store(lock_wait, Ordering::Relaxed);
+        self.lock_wait_us_total
+            .fetch_add(lock_wait, Ordering::Relaxed);
+    }
+
+    // When the tick thread last ran a tick, paused or not
+    pub fn last_tick(&self) -> Option<Instant> {
+        *self.last_tick.lock().unwrap()
+    }
+
+    pub fn last_tick_at_ms(&self) -> Option<u64> {
+        match self.last_tick_at_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(ms),
+        }
+    }
+
+    fn ticks(&self) -> f64 {
+        self.ticks.load(Ordering::Relaxed) as f64
+    }
+
+    fn last_frame_time(&self) -> f64 {
+        seconds(&self.last_frame_time_us)
+    }
+
+    fn frame_time_total(&self) -> f64 {
+        seconds(&self.frame_time_us_total)
+    }
+
+    fn last_lock_wait(&self) -> f64 {
+        seconds(&self.last_lock_wait_us)
+    }
+
+    fn lock_wait_total(&self) -> f64 {
+        seconds(&self.lock_wait_us_total)
+    }
+}
+
+// What a world's websocket sessions cost to serve
+#[derive(Default)]
+pub struct SessionMetrics {
+    connected: AtomicU64,
+    frames: AtomicU64,
+    render_us_total: AtomicU64,
+    serialize_us_total: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+// Counts a session as connected until dropped
+pub struct ConnectedSession<'a>(&'a SessionMetrics);
+
+impl Drop for ConnectedSession<'_> {
+    fn drop(&mut self) {
+        self.0.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl SessionMetrics {
+    pub fn connect(&self) -> ConnectedSession<'_> {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+        ConnectedSession(self)
+    }
+
+    // A frame of the world sent to one session
+    pub fn record_frame(&self, render_time: Duration, serialize_time: Duration) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.render_us_total
+            .fetch_add(render_time.as_micros() as u64, Ordering::Relaxed);
+        self.serialize_us_total
+            .fetch_add(serialize_time.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn connected(&self) -> f64 {
+        self.connected.load(Ordering::Relaxed) as f64
+    }
+
+    fn frames(&self) -> f64 {
+        self.frames.load(Ordering::Relaxed) as f64
+    }
+
+    fn render_total(&self) -> f64 {
+        seconds(&self.render_us_total)
+    }
+
+    fn serialize_total(&self) -> f64 {
+        seconds(&self.serialize_us_total)
+    }
+
+    fn bytes_sent(&self) -> f64 {
+        self.bytes_sent.load(Ordering::Relaxed) as f64
+    }
+}
+
+type TickMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&TickMetrics) -> f64,
+);
+
+const TICK_METRICS: [TickMetric; 5] = [
+    (
+        "garden_ticks_total",
+        "counter",
+        "Ticks run by the tick thread",
+        TickMetrics::ticks,
+    ),
+    (
+        "garden_frame_time_seconds",
+        "gauge",
+        "Time taken by the last tick",
+        TickMetrics::last_frame_time,
+    ),
+    (
+        "garden_frame_time_seconds_total",
+        "counter",
+        "Time spent running ticks",
+        TickMetrics::frame_time_total,
+    ),
+    (
+        "garden_lock_wait_seconds",
+        "gauge",
+        "Time the last tick waited for the world",
+        TickMetrics::last_lock_wait,
+    ),
+    (
+        "garden_lock_wait_seconds_total",
+        "counter",
+        "Time ticks spent waiting for the world",
+        TickMetrics::lock_wait_total,
+    ),
+];
+
+type SessionMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&SessionMetrics) -> f64,
+);
+
+const SESSION_METRICS: [SessionMetric; 5] = [
+    (
+        "garden_websocket_clients",
+        "gauge",
+        "Open websocket sessions",
+        SessionMetrics::connected,
+    ),
+    (
+        "garden_websocket_frames_total",
+        "counter",
+        "Frames of the world sent to websocket sessions",
+        SessionMetrics::frames,
+    ),
+    (
+        "garden_render_seconds_total",
+        "counter",
+        "Time spent rendering the world for websocket sessions",
+        SessionMetrics::render_total,
+    ),
+    (
+        "garden_serialize_seconds_total",
+        "counter",
+        "Time spent encoding rendered frames",
+        SessionMetrics::serialize_total,
+    ),
+    (
+        "garden_websocket_sent_bytes_total",
+        "counter",
+        "Bytes of websocket messages sent, frames and replies alike",
+        SessionMetrics::bytes_sent,
+    ),
+];
+
+type PoolMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&PoolStats) -> usize,
+);
+
+const POOL_METRICS: [PoolMetric; 4] = [
+    (
+        "garden_pool_threads",
+        "gauge",
+        "Threads the pool runs jobs on",
+        |s| s.size,
+    ),
+    (
+        "garden_pool_queued_jobs",
+        "gauge",
+        "Jobs waiting for a thread",
+        |s| s.queued_jobs,
+    ),
+    (
+        "garden_pool_busy_threads",
+        "gauge",
+        "Threads running a job",
+        |s| s.busy_workers,
+    ),
+    (
+        "garden_pool_panicked_jobs_total",
+        "counter",
+        "Jobs that panicked",
+        |s| s.panicked_jobs,
+    ),
+];
+
+// Each world's tick metrics, labelled with the world's name
+pub fn render_tick_metrics(out: &mut String, worlds: &[(&str, &TickMetrics)]) {
+    for (name, kind, help, value) in TICK_METRICS.iter() {
+        write_header(out, name, kind, help);
+        for (world, metrics) in worlds {
+            // Writing to a String can't fail
+            let _ = writeln!(out, "{}{{world=\"{}\"}} {}", name, world, value(metrics));
+        }
+    }
+
+    let name = "garden_tick_duration_seconds";
+    write_header(out, name, "histogram", "Time taken by ticks");
+    for (world, metrics) in worlds {
+        let buckets = TICK_DURATION_BUCKETS.iter();
+        for (bound, count) in buckets.zip(metrics.tick_duration_buckets.iter()) {
+            let count = count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{world=\"{}\",le=\"{}\"}} {}",
+                name, world, bound, count
+            );
+        }
+        let ticks = metrics.ticks();
+        let _ = writeln!(
+            out,
+            "{}_bucket{{world=\"{}\",le=\"+Inf\"}} {}",
+            name, world, ticks
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{world=\"{}\"}} {}",
+            name,
+            world,
+            metrics.frame_time_total()
+        );
+        let _ = writeln!(out, "{}_count{{world=\"{}\"}} {}", name, world, ticks);
+    }
+}
+
+// Each world's websocket metrics, labelled with the world's name
+pub fn render_session_metrics(out: &mut String, worlds: &[(&str, &SessionMetrics)]) {
+    for (name, kind, help, value) in SESSION_METRICS.iter() {
+        write_header(out, name, kind, help);
+        for (world, metrics) in worlds {
+            let _ = writeln!(out, "{}{{world=\"{}\"}} {}", name, world, value(metrics));
+        }
+    }
+}
+
+// Entities in each world by kind, spawners included
+pub fn render_entity_counts(out: &mut String, worlds: &[(&str, BTreeMap<String, usize>)]) {
+    let name = "garden_entities";
+    write_header(out, name, "gauge", "Entities in the world by kind");
+    for (world, counts) in worlds {
+        for (kind, count) in counts {
+            let _ = writeln!(
+                out,
+                "{}{{world=\"{}\",kind=\"{}\"}} {}",
+                name, world, kind, count
+            );
+        }
+    }
+}
+
+// Thread pools labelled by what they run
+pub fn render_pool_metrics(out: &mut String, pools: &[(&str, PoolStats)]) {
+    for (name, kind, help, value) in POOL_METRICS.iter() {
+        write_header(out, name, kind, help);
+        for (pool, stats) in pools {
+            let _ = writeln!(out, "{}{{pool=\"{}\"}} {}", name, pool, value(stats));
+        }
+    }
+}
+
+fn seconds(micros: &AtomicU64) -> f64 {
+    micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_tick_metrics() {
+        let metrics = TickMetrics::default();
+        metrics.record_tick(Duration::from_millis(3), Duration::from_millis(1));
+        metrics.record_tick(Duration::from_millis(5), Duration::from_millis(0));
+
+        let mut out = String::new();
+        render_tick_metrics(
+            &mut out,
+            &[("default", &metrics), ("lab", &TickMetrics::default())],
+        );
+
+        assert!(out.contains(
+            "# TYPE garden_ticks_total counter\ngarden_ticks_total{world=\"default\"} 2\ngarden_ticks_total{world=\"lab\"} 0\n"
+        ));
+        assert!(out.contains("garden_frame_time_seconds{world=\"default\"} 0.005\n"));
+        assert!(out.contains("garden_frame_time_seconds_total{world=\"default\"} 0.008\n"));
+        assert!(out.contains("garden_lock_wait_seconds_total{world=\"default\"} 0.001\n"));
+        assert!(out.contains(
+            "garden_tick_duration_seconds_bucket{world=\"default\",le=\"0.0025\"} 0\n\
+             garden_tick_duration_seconds_bucket{world=\"default\",le=\"0.005\"} 2\n"
+        ));
+        assert!(out.contains("garden_tick_duration_seconds_count{world=\"lab\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_session_metrics() {
+        let metrics = SessionMetrics::default();
+        let session = metrics.connect();
+        metrics.connect();
+        metrics.record_frame(Duration::from_millis(2), Duration::from_millis(1));
+        metrics.record_sent(1_500);
+
+        let mut out = String::new();
+        render_session_metrics(&mut out, &[("default", &metrics)]);
+        assert!(out.contains("garden_websocket_clients{world=\"default\"} 1\n"));
+        assert!(out.contains("garden_render_seconds_total{world=\"default\"} 0.002\n"));
+        assert!(out.contains("garden_websocket_sent_bytes_total{world=\"default\"} 1500\n"));
+
+        drop(session);
+        assert_eq!(metrics.connected(), 0.0);
+    }
+}
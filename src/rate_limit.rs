@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+// Lets an action through at most once per `interval`. Each websocket session
+// has its own, so limits are per connection.
+pub struct RateLimiter {
+    interval: Duration,
+    last_allowed: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> RateLimiter {
+        RateLimiter {
+            interval,
+            last_allowed: None,
+        }
+    }
+
+    // Err holds how long until the action is allowed again
+    pub fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(last_allowed) = self.last_allowed {
+            let elapsed = now.saturating_duration_since(last_allowed);
+            if elapsed < self.interval {
+                return Err(self.interval - elapsed);
+            }
+        }
+        self.last_allowed = Some(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_one_action_per_interval() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(Duration::from_millis(1000));
+
+        assert_eq!(limiter.try_acquire(start), Ok(()));
+        assert_eq!(
+            limiter.try_acquire(start + Duration::from_millis(400)),
+            Err(Duration::from_millis(600))
+        );
+        // Rejected attempts don't push the next allowed one back
+        assert_eq!(
+            limiter.try_acquire(start + Duration::from_millis(1000)),
+            Ok(())
+        );
+
+        let mut unlimited = RateLimiter::new(Duration::from_millis(0));
+        assert_eq!(unlimited.try_acquire(start), Ok(()));
+        assert_eq!(unlimited.try_acquire(start), Ok(()));
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::logging;
+use crate::scheduler::Scheduler;
+use crate::shutdown::Shutdown;
+use crate::simulation::{ConfiguredWorld, SharedWorld};
+use crate::thread_pool::{PoolStats, ThreadPool};
+use crate::world::World;
+
+// Served on `/` and `/websocket`, and saved on shutdown
+pub const DEFAULT_WORLD: &str = "default";
+const MAX_NAME_LENGTH: usize = 64;
+// Longest the tick thread sleeps, so shutdowns and speed changes are noticed promptly
+const MAX_TICK_SLEEP_MS: u64 = 50;
+
+// A world and the thread ticking it
+pub struct RunningWorld {
+    pub name: String,
+    pub shared: Arc<SharedWorld>,
+    // Requested when the world is destroyed or the server shuts down
+    pub stop: Shutdown,
+    tick_thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+// What `GET /worlds` lists for each world
+#[derive(Serialize, Debug, PartialEq)]
+pub struct WorldSummary {
+    pub name: String,
+    pub tick: u64,
+    pub width: i32,
+    pub height: i32,
+    pub entities: usize,
+    pub paused: bool,
+}
+
+// Every world on the server, by name. Each ticks on its own thread and
+// schedule; they share one pool for planning entity updates.
+pub struct WorldRegistry {
+    worlds: RwLock<BTreeMap<String, Arc<RunningWorld>>>,
+    simulation_pool: Arc<ThreadPool>,
+    shutdown: Shutdown,
+    config: Config,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RegistryError {
+    InvalidName(String),
+    AlreadyExists(String),
+    NotFound(String),
+    // The default world backs the original routes so it can't be destroyed
+    Protected(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::InvalidName(name) => write!(
+                f,
+                "Invalid world name {:?}, use up to {} letters, digits, '-' or '_'",
+                name, MAX_NAME_LENGTH
+            ),
+            RegistryError::AlreadyExists(name) => write!(f, "World {} already exists", name),
+            RegistryError::NotFound(name) => write!(f, "No world called {}", name),
+            RegistryError::Protected(name) => write!(f, "World {} can't be destroyed", name),
+        }
+    }
+}
+
+impl WorldRegistry {
+    pub fn new(config: &Config, shutdown: &Shutdown) -> WorldRegistry {
+        WorldRegistry {
+            worlds: RwLock::new(BTreeMap::new()),
+            simulation_pool: Arc::new(ThreadPool::new(config.simulation_threads)),
+            shutdown: shutdown.clone(),
+            config: config.clone(),
+        }
+    }
+
+    // Starts ticking `world` straight away
+    pub fn create(&self, name: &str, world: World) -> Result<Arc<RunningWorld>, RegistryError> {
+        if !is_valid_name(name) {
+            return Err(RegistryError::InvalidName(String::from(name)));
+        }
+        let mut worlds = self.worlds.write().unwrap();
+        if worlds.contains_key(name) {
+            return Err(RegistryError::AlreadyExists(String::from(name)));
+        }
+
+        let configured_world = ConfiguredWorld::new(
+            world,
+            self.config.tick_rate_ms,
+            self.config.randomizer(),
+            self.config.history(),
+        );
+        let shared = Arc::new(SharedWorld::new(configured_world));
+        let stop = self.shutdown.child();
+        let tick_thread = {
+            let shared = Arc::clone(&shared);
+            let stop = stop.clone();
+            let pool = Arc::clone(&self.simulation_pool);
+            let max_catch_up_ticks = self.config.max_catch_up_ticks;
+            let name = String::from(name);
+            thread::spawn(move || {
+                let _world = logging::field("world", &name);
+                run_ticks(&shared, &stop, &pool, max_catch_up_ticks)
+            })
+        };
+        let running = Arc::new(RunningWorld {
+            name: String::from(name),
+            shared,
+            stop,
+            tick_thread: Mutex::new(Some(tick_thread)),
+        });
+        worlds.insert(String::from(name), Arc::clone(&running));
+        log::info!("Created world {}", name);
+        Ok(running)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RunningWorld>> {
+        self.worlds.read().unwrap().get(name).cloned()
+    }
+
+    // Sorted by name
+    pub fn list(&self) -> Vec<WorldSummary> {
+        let worlds = self.worlds.read().unwrap();
+        worlds
+            .values()
+            .map(|running| {
+                let frame = running.shared.current();
+                WorldSummary {
+                    name: running.name.clone(),
+                    tick: frame.world.current_tick(),
+                    width: frame.world.width,
+                    height: frame.world.height,
+                    entities: frame.world.entity_count(),
+                    paused: !frame.world.is_active(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn simulation_pool_stats(&self) -> PoolStats {
+        self.simulation_pool.stats()
+    }
+
+    pub fn all(&self) -> Vec<Arc<RunningWorld>> {
+        self.worlds.read().unwrap().values().cloned().collect()
+    }
+
+    // Stops the world's tick thread and closes its websockets
+    pub fn destroy(&self, name: &str) -> Result<(), RegistryError> {
+        if name == DEFAULT_WORLD {
+            return Err(RegistryError::Protected(String::from(name)));
+        }
+        let running = self
+            .worlds
+            .write()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| RegistryError::NotFound(String::from(name)))?;
+        running.stop_and_join();
+        log::info!("Destroyed world {}", name);
+        Ok(())
+    }
+
+    // Waits for every tick thread, once the server has been asked to shut down
+    pub fn join_all(&self) {
+        for running in self.all() {
+            running.stop_and_join();
+        }
+    }
+}
+
+impl RunningWorld {
+    fn stop_and_join(&self) {
+        self.stop.request();
+        if let Some(tick_thread) = self.tick_thread.lock().unwrap().take() {
+            if tick_thread.join().is_err() {
+                log::error!("Tick thread for world {} panicked", self.name);
+            }
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn run_ticks(shared: &SharedWorld, stop: &Shutdown, pool: &ThreadPool, max_catch_up_ticks: u32) {
+    let mut scheduler = Scheduler::new(max_catch_up_ticks, Instant::now());
+    while !stop.is_requested() {
+        let frame = shared.current();
+        let timestep = Duration::from_millis(frame.tick_rate);
+        let ticks_due = scheduler.ticks_due(Instant::now(), timestep, frame.speed);
+        for _ in 0..ticks_due {
+            let start = Instant::now();
+            // Readers work from the last published frame, so the only
+            // thing this can wait on is a control command
+            let lock_wait = shared.update(|w| {
+                let lock_wait = start.elapsed();
+                let active = w.world.is_active();
+                let _tick = active.then(|| logging::field("tick", w.world.current_tick() + 1));
+                if w.tick(Some(pool)) {
+                    let tick = w.world.current_tick();
+                    shared.events.publish(tick, w.world.events());
+                }
+                lock_wait
+            });
+            shared.metrics.record_tick(start.elapsed(), lock_wait);
+        }
+
+        let until_next_tick = scheduler.until_next_tick(timestep, frame.speed);
+        thread::sleep(until_next_tick.min(Duration::from_millis(MAX_TICK_SLEEP_MS)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_and_destroy_worlds() {
+        let shutdown = Shutdown::new();
+        let registry = WorldRegistry::new(&Config::default(), &shutdown);
+        registry.create(DEFAULT_WORLD, World::default()).unwrap();
+        let lab = registry.create("lab", World::new(5, 5)).unwrap();
+
+        assert_eq!(
+            registry.create("lab", World::new(5, 5)).err(),
+            Some(RegistryError::AlreadyExists(String::from("lab")))
+        );
+        assert_eq!(
+            registry.create("../etc", World::new(5, 5)).err(),
+            Some(RegistryError::InvalidName(String::from("../etc")))
+        );
+        let names: Vec<String> = registry.list().into_iter().map(|w| w.name).collect();
+        assert_eq!(names, vec!["default", "lab"]);
+
+        registry.destroy("lab").unwrap();
+        assert!(lab.stop.is_requested());
+        assert!(registry.get("lab").is_none());
+        assert_eq!(
+            registry.destroy(DEFAULT_WORLD),
+            Err(RegistryError::Protected(String::from(DEFAULT_WORLD)))
+        );
+
+        shutdown.request();
+        registry.join_all();
+    }
+}
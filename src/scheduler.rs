@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 16.0;
+
+// How fast simulated time passes relative to real time.
+// Sent as `{"multiplier": 2.0}` or `"as_fast_as_possible"`.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Speed {
+    Multiplier(f64),
+    // Ticks back to back, leaving only enough room to notice a shutdown
+    AsFastAsPossible,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Multiplier(1.0)
+    }
+}
+
+// Decides when the tick thread runs a tick. Time is accumulated and spent in
+// whole timesteps, so simulated time keeps pace with real time (times the
+// speed) even when individual ticks run late. A slow tick is made up for by
+// running several back to back, up to `max_catch_up` at a time; anything past
+// that is dropped rather than letting the backlog grow forever.
+pub struct Scheduler {
+    max_catch_up: u32,
+    lag: Duration,
+    last_checked: Instant,
+}
+
+impl Scheduler {
+    pub fn new(max_catch_up: u32, now: Instant) -> Scheduler {
+        Scheduler {
+            max_catch_up,
+            lag: Duration::from_secs(0),
+            last_checked: now,
+        }
+    }
+
+    // How many ticks are due at `now`
+    pub fn ticks_due(&mut self, now: Instant, timestep: Duration, speed: Speed) -> u32 {
+        let elapsed = now.saturating_duration_since(self.last_checked);
+        self.last_checked = now;
+        let multiplier = match speed {
+            Speed::Multiplier(multiplier) => multiplier,
+            Speed::AsFastAsPossible => {
+                self.lag = Duration::from_secs(0);
+                return 1;
+            }
+        };
+
+        self.lag += elapsed.mul_f64(multiplier);
+        let due = (self.lag.as_nanos() / timestep.as_nanos().max(1)) as u32;
+        if due > self.max_catch_up {
+            log::warn!(
+                "Simulation is {} ticks behind, skipping {}",
+                due,
+                due - self.max_catch_up
+            );
+            self.lag = Duration::from_secs(0);
+            return self.max_catch_up;
+        }
+        self.lag -= timestep * due;
+        due
+    }
+
+    // Real time until the next tick is due
+    pub fn until_next_tick(&self, timestep: Duration, speed: Speed) -> Duration {
+        match speed {
+            Speed::Multiplier(multiplier) => timestep.saturating_sub(self.lag).div_f64(multiplier),
+            Speed::AsFastAsPossible => Duration::from_secs(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMESTEP: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn test_keeps_pace_with_real_time() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(5, start);
+        let normal = Speed::default();
+
+        assert_eq!(
+            scheduler.ticks_due(start + TIMESTEP / 2, TIMESTEP, normal),
+            0
+        );
+        assert_eq!(scheduler.until_next_tick(TIMESTEP, normal), TIMESTEP / 2);
+        // Lag carries over, a late tick doesn't push the next one back
+        assert_eq!(
+            scheduler.ticks_due(start + TIMESTEP * 5 / 4, TIMESTEP, normal),
+            1
+        );
+        assert_eq!(
+            scheduler.until_next_tick(TIMESTEP, normal),
+            TIMESTEP * 3 / 4
+        );
+        assert_eq!(
+            scheduler.ticks_due(start + TIMESTEP * 4, TIMESTEP, normal),
+            3
+        );
+    }
+
+    #[test]
+    fn test_catch_up_is_limited() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(5, start);
+
+        let due = scheduler.ticks_due(start + TIMESTEP * 20, TIMESTEP, Speed::default());
+        assert_eq!(due, 5);
+        // The rest of the backlog was dropped
+        assert_eq!(
+            scheduler.until_next_tick(TIMESTEP, Speed::default()),
+            TIMESTEP
+        );
+    }
+
+    #[test]
+    fn test_speed_multiplier() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(100, start);
+
+        let due = scheduler.ticks_due(start + TIMESTEP * 2, TIMESTEP, Speed::Multiplier(4.0));
+        assert_eq!(due, 8);
+        let due = scheduler.ticks_due(start + TIMESTEP * 6, TIMESTEP, Speed::Multiplier(0.25));
+        assert_eq!(due, 1);
+        assert_eq!(
+            scheduler.until_next_tick(TIMESTEP, Speed::AsFastAsPossible),
+            Duration::from_secs(0)
+        );
+    }
+}
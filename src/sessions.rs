@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// Websocket sessions live as long as the browser tab, so each one gets a
+// thread of its own rather than holding a request worker hostage.
+pub struct SessionPool {
+    max_sessions: usize,
+    active: Arc<AtomicUsize>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+// Decrements the active count however the session ends, including panics
+struct ActiveSession(Arc<AtomicUsize>);
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SessionPool {
+    pub fn new(max_sessions: usize) -> SessionPool {
+        SessionPool {
+            max_sessions,
+            active: Arc::new(AtomicUsize::new(0)),
+            threads: Vec::new(),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    // Returns false without running the session when the pool is full.
+    // Only called from the listener thread, so the check can't race another spawn.
+    pub fn spawn<F>(&mut self, session: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.threads.retain(|thread| !thread.is_finished());
+        if self.active() >= self.max_sessions {
+            return false;
+        }
+
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let active_session = ActiveSession(Arc::clone(&self.active));
+        let thread = thread::spawn(move || {
+            let _active_session = active_session;
+            session();
+        });
+        self.threads.push(thread);
+        true
+    }
+}
+
+impl Drop for SessionPool {
+    // Sessions watch for shutdown themselves, this only waits for them
+    fn drop(&mut self) {
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                log::error!("Websocket session panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_sessions_over_limit() {
+        use std::sync::mpsc;
+
+        let mut sessions = SessionPool::new(2);
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(std::sync::Mutex::new(released));
+        for _ in 0..2 {
+            let released = Arc::clone(&released);
+            assert!(sessions.spawn(move || {
+                let _ = released.lock().unwrap().recv();
+            }));
+        }
+
+        assert!(!sessions.spawn(|| ()));
+        assert_eq!(sessions.active(), 2);
+
+        drop(release);
+        drop(sessions);
+    }
+}
@@ -0,0 +1,76 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Shared flag checked by every long running loop (the tick thread, the
+// listener and open websockets) so the server can stop cleanly
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    // A child also counts as requested once its parent is
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.load(Ordering::SeqCst))
+    }
+
+    // For things that can stop on their own, like a single world, as well as
+    // with the rest of the server. Only one level deep.
+    pub fn child(&self) -> Shutdown {
+        Shutdown {
+            requested: Arc::new(AtomicBool::new(false)),
+            parent: Some(Arc::clone(&self.requested)),
+        }
+    }
+
+    // SIGINT and SIGTERM request a shutdown. A second signal gives up on
+    // waiting and exits immediately.
+    pub fn install_signal_handler(&self) {
+        let shutdown = self.clone();
+        let result = ctrlc::set_handler(move || {
+            if shutdown.is_requested() {
+                log::warn!("Received second shutdown signal, exiting immediately");
+                process::exit(130);
+            }
+            log::info!("Received shutdown signal");
+            shutdown.request();
+        });
+        if let Err(e) = result {
+            log::error!("Unable to install shutdown signal handler: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_follows_parent() {
+        let server = Shutdown::new();
+        let first = server.child();
+        let second = server.child();
+
+        first.request();
+        assert!(first.is_requested());
+        assert!(!second.is_requested());
+        assert!(!server.is_requested());
+
+        server.request();
+        assert!(second.is_requested());
+    }
+}
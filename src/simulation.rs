@@ -0,0 +1,444 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+
+use crate::event_bus::{EventBus, EVENT_BUS_TICKS};
+use crate::history::{History, Input, Replay};
+use crate::logging;
+use crate::metrics::{SessionMetrics, TickMetrics};
+use crate::scheduler::Speed;
+use crate::thread_pool::ThreadPool;
+use crate::world::population::PopulationSeries;
+use crate::world::{EntityError, World};
+
+// Ticks of population stats kept for `/stats` and charts
+pub const POPULATION_SERIES_TICKS: usize = 3_600;
+// Tick periods without a tick before the tick thread counts as stalled
+pub const STALLED_AFTER_TICKS: u32 = 5;
+// Never stalled sooner than this, so one slow tick of a fast world doesn't count
+const MIN_STALL_MS: u64 = 2_000;
+
+// Everything the tick thread and control commands change
+pub struct ConfiguredWorld {
+    pub world: World,
+    pub tick_rate: u64,
+    pub speed: Speed,
+    pub randomizer: rand_pcg::Pcg32,
+    // The world pauses itself once this is met
+    run_until: Option<Until>,
+    last_stop: Option<Stop>,
+    pub history: History,
+    pub population: PopulationSeries,
+    // Applied at the start of the next tick
+    queued_inputs: Vec<Input>,
+}
+
+// When a run started by a control command should stop.
+// Sent as `{"tick": 500}`, `"no_eaters"` or `{"eaters_above": 40}`.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Until {
+    Tick(u64),
+    NoEaters,
+    EatersAbove(usize),
+}
+
+// A run that reached its goal. Numbered so sessions can tell a new stop from
+// one they've already reported.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct Stop {
+    #[serde(skip)]
+    pub id: u64,
+    pub tick: u64,
+    pub until: Until,
+}
+
+// What readers see: the world as of the end of a tick. Never changes once
+// published, a newer frame replaces it instead.
+pub struct Frame {
+    pub world: World,
+    pub tick_rate: u64,
+    pub speed: Speed,
+    pub last_stop: Option<Stop>,
+}
+
+// The simulation is double buffered. Writers take turns on the mutable state
+// while readers grab the latest published frame without taking any lock, so
+// rendering a websocket frame never waits on a tick and a tick never waits on
+// readers.
+pub struct SharedWorld {
+    state: Mutex<ConfiguredWorld>,
+    current: ArcSwap<Frame>,
+    pub metrics: TickMetrics,
+    pub events: EventBus,
+    pub sessions: SessionMetrics,
+    // Stands in for the last tick until the first one runs
+    created_at: Instant,
+}
+
+// Whether a world's tick thread is keeping up, served on /readyz
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Readiness {
+    pub ready: bool,
+    pub tick: u64,
+    // Unix time in milliseconds, null before the first tick
+    pub last_tick_at_ms: Option<u64>,
+    // Why the world isn't ready
+    pub problem: Option<String>,
+}
+
+impl SharedWorld {
+    pub fn new(configured_world: ConfiguredWorld) -> SharedWorld {
+        let frame = Frame::of(&configured_world);
+        SharedWorld {
+            state: Mutex::new(configured_world),
+            current: ArcSwap::from_pointee(frame),
+            metrics: TickMetrics::default(),
+            events: EventBus::new(EVENT_BUS_TICKS),
+            sessions: SessionMetrics::default(),
+            created_at: Instant::now(),
+        }
+    }
+
+    pub fn readiness(&self, now: Instant) -> Readiness {
+        let frame = self.current();
+        let last_tick = self.metrics.last_tick().unwrap_or(self.created_at);
+        let since_last_tick = now.saturating_duration_since(last_tick);
+        let tick_period = match frame.speed {
+            Speed::Multiplier(multiplier) => {
+                Duration::from_millis(frame.tick_rate).div_f64(multiplier)
+            }
+            Speed::AsFastAsPossible => Duration::from_millis(frame.tick_rate),
+        };
+        let stall_after =
+            (tick_period * STALLED_AFTER_TICKS).max(Duration::from_millis(MIN_STALL_MS));
+
+        let problem = if self.state.is_poisoned() {
+            Some(String::from("The world's lock is poisoned"))
+        } else if since_last_tick > stall_after {
+            Some(format!(
+                "No tick for {}ms, expected one every {}ms",
+                since_last_tick.as_millis(),
+                tick_period.as_millis()
+            ))
+        } else {
+            None
+        };
+        Readiness {
+            ready: problem.is_none(),
+            tick: frame.world.current_tick(),
+            last_tick_at_ms: self.metrics.last_tick_at_ms(),
+            problem,
+        }
+    }
+
+    // The latest published frame
+    pub fn current(&self) -> Arc<Frame> {
+        self.current.load_full()
+    }
+
+    // Reads the mutable state without publishing a frame
+    pub fn inspect<F, T>(&self, read: F) -> T
+    where
+        F: FnOnce(&ConfiguredWorld) -> T,
+    {
+        read(&self.state.lock().unwrap())
+    }
+
+    // Tries an input on a copy of the latest frame, so obvious mistakes can be
+    // reported straight away rather than only showing up in the log. The input
+    // can still be dropped if the world changes before the next tick boundary.
+    pub fn check_input(&self, input: &Input) -> Result<(), EntityError> {
+        input.apply(&mut self.current().world.clone())
+    }
+
+    // Changes the world and publishes the result as the new current frame
+    pub fn update<F, T>(&self, change: F) -> T
+    where
+        F: FnOnce(&mut ConfiguredWorld) -> T,
+    {
+        let mut state = self.state.lock().unwrap();
+        let result = change(&mut state);
+        self.current.store(Arc::new(Frame::of(&state)));
+        result
+    }
+}
+
+impl ConfiguredWorld {
+    pub fn new(
+        world: World,
+        tick_rate: u64,
+        randomizer: rand_pcg::Pcg32,
+        mut history: History,
+    ) -> ConfiguredWorld {
+        history.record_tick(&world, &randomizer);
+        let mut population = PopulationSeries::new(POPULATION_SERIES_TICKS);
+        population.record(world.population());
+        ConfiguredWorld {
+            world,
+            tick_rate,
+            speed: Speed::default(),
+            randomizer,
+            run_until: None,
+            last_stop: None,
+            history,
+            population,
+            queued_inputs: vec![],
+        }
+    }
+
+    // Returns whether the world moved, paused worlds and skipped ticks don't
+    pub fn tick(&mut self, pool: Option<&ThreadPool>) -> bool {
+        self.apply_queued_inputs();
+        let ticked = self.world.update_if_active(&mut self.randomizer, pool);
+        if ticked {
+            self.history.record_tick(&self.world, &self.randomizer);
+            self.population.record(self.world.population());
+            self.stop_if_done();
+        }
+        ticked
+    }
+
+    // Changes from outside the simulation wait for the next tick boundary,
+    // so the world is never changed halfway through a tick
+    pub fn queue_input(&mut self, input: Input) {
+        self.queued_inputs.push(input);
+    }
+
+    fn apply_queued_inputs(&mut self) {
+        for input in std::mem::take(&mut self.queued_inputs) {
+            let _entity = match input {
+                Input::Remove { id } | Input::Patch { id, .. } => {
+                    Some(logging::field("entity_id", id))
+                }
+                Input::Spawn { .. } => None,
+            };
+            match input.apply(&mut self.world) {
+                Ok(()) => self.history.record_input(self.world.current_tick(), input),
+                Err(e) => log::warn!("Dropped {:?}: {}", input, e),
+            }
+        }
+    }
+
+    // Rewinds the world to how it was at `tick` and pauses it there. Whatever
+    // happened after that tick is forgotten.
+    pub fn fork(&mut self, tick: u64) -> Result<(), String> {
+        let replay = self.replay_to(tick)?;
+        let (world, randomizer) = replay.run();
+        self.world = world;
+        self.randomizer = randomizer;
+        self.history.truncate_after(tick);
+        self.population.truncate_after(tick);
+        self.pause();
+        log::info!("Forked the world from tick {}", tick);
+        Ok(())
+    }
+
+    pub fn replay_to(&self, tick: u64) -> Result<Replay, String> {
+        if tick > self.world.current_tick() {
+            return Err(format!("Tick {} hasn't happened yet", tick));
+        }
+        self.history.replay_to(tick).ok_or_else(|| {
+            format!(
+                "Tick {} is no longer in the history, the oldest is {}",
+                tick,
+                self.history.oldest_tick().unwrap_or(0)
+            )
+        })
+    }
+
+    // Runs the world at the scheduled speed until `until` is met, then pauses it
+    pub fn run_until(&mut self, until: Until) {
+        self.run_until = Some(until);
+        self.world.unpause();
+        self.stop_if_done();
+    }
+
+    // Manual pauses and resumes cancel any run in progress
+    pub fn pause(&mut self) {
+        self.run_until = None;
+        self.world.pause();
+    }
+
+    pub fn resume(&mut self) {
+        self.run_until = None;
+        self.world.unpause();
+    }
+
+    fn stop_if_done(&mut self) {
+        let until = match self.run_until {
+            Some(until) if until.is_met(&self.world) => until,
+            _ => return,
+        };
+        self.world.pause();
+        self.run_until = None;
+        let id = self.last_stop.map_or(1, |stop| stop.id + 1);
+        let tick = self.world.current_tick();
+        log::info!("Stopped at tick {}, reached {:?}", tick, until);
+        self.last_stop = Some(Stop { id, tick, until });
+    }
+}
+
+impl Until {
+    fn is_met(&self, world: &World) -> bool {
+        match self {
+            Until::Tick(tick) => world.current_tick() >= *tick,
+            Until::NoEaters => world.eater_count() == 0,
+            Until::EatersAbove(count) => world.eater_count() > *count,
+        }
+    }
+}
+
+impl Frame {
+    fn of(configured_world: &ConfiguredWorld) -> Frame {
+        Frame {
+            // Entities are shared with the mutable world, not copied
+            world: configured_world.world.clone(),
+            tick_rate: configured_world.tick_rate,
+            speed: configured_world.speed,
+            last_stop: configured_world.last_stop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::scenario::EntitySpec;
+    use crate::world::Position;
+    use rand_core::SeedableRng;
+    use std::panic::AssertUnwindSafe;
+
+    #[test]
+    fn test_frames_are_not_changed_by_updates() {
+        let shared = SharedWorld::new(ConfiguredWorld::new(
+            World::default(),
+            100,
+            rand_pcg::Pcg32::seed_from_u64(1),
+            History::new(10, 10),
+        ));
+        let before = shared.current();
+
+        // Holding on to a frame doesn't stop the world moving on
+        shared.update(|w| {
+            for _ in 0..20 {
+                w.world.update(&mut w.randomizer);
+            }
+            w.tick_rate = 50;
+        });
+
+        assert_eq!(before.tick_rate, 100);
+        assert_eq!(before.world.snapshot(), World::default().snapshot());
+        assert_eq!(shared.current().tick_rate, 50);
+        assert_ne!(shared.current().world.snapshot(), before.world.snapshot());
+    }
+
+    #[test]
+    fn test_run_until_pauses_when_met() {
+        let mut w = ConfiguredWorld::new(
+            World::default(),
+            100,
+            rand_pcg::Pcg32::seed_from_u64(1),
+            History::new(10, 10),
+        );
+        w.pause();
+        w.run_until(Until::Tick(3));
+        while w.world.is_active() {
+            w.tick(None);
+        }
+        assert_eq!(w.world.current_tick(), 3);
+        assert_eq!(w.last_stop.map(|stop| stop.tick), Some(3));
+
+        // Already met, stops straight away without running a tick
+        w.run_until(Until::EatersAbove(0));
+        assert!(!w.world.is_active());
+        assert_eq!(w.world.current_tick(), 3);
+        assert_eq!(w.last_stop.map(|stop| stop.id), Some(2));
+    }
+
+    #[test]
+    fn test_fork_rewinds_the_world() {
+        let mut w = ConfiguredWorld::new(
+            World::default(),
+            100,
+            rand_pcg::Pcg32::seed_from_u64(1),
+            History::new(10, 10),
+        );
+        let mut past = vec![];
+        for _ in 0..25 {
+            past.push(w.world.snapshot());
+            w.tick(None);
+        }
+
+        assert!(w.fork(30).is_err());
+        w.fork(13).unwrap();
+        assert_eq!(w.world.snapshot(), past[13]);
+        assert!(!w.world.is_active());
+
+        // Carries on exactly as it did the first time round
+        w.resume();
+        for _ in 13..25 {
+            w.tick(None);
+        }
+        w.fork(20).unwrap();
+        assert_eq!(w.world.snapshot(), past[20]);
+    }
+
+    #[test]
+    fn test_inputs_wait_for_the_tick_boundary() {
+        let mut w = ConfiguredWorld::new(
+            World::new(5, 5),
+            100,
+            rand_pcg::Pcg32::seed_from_u64(1),
+            History::new(10, 10),
+        );
+        let food = EntitySpec::Food {
+            position: Position { x: 1, y: 1 },
+        };
+        w.queue_input(Input::Spawn {
+            entity: food.clone(),
+        });
+        // Lands in an occupied cell by the time it's applied, so it's dropped
+        w.queue_input(Input::Spawn { entity: food });
+        assert_eq!(w.world.entity_count(), 0);
+
+        w.tick(None);
+        assert_eq!(w.world.entity_count(), 1);
+        let (replayed, _) = w.replay_to(1).unwrap().run();
+        assert_eq!(replayed.snapshot(), w.world.snapshot());
+    }
+
+    #[test]
+    fn test_readiness_notices_stalls_and_poisoning() {
+        let shared = SharedWorld::new(ConfiguredWorld::new(
+            World::default(),
+            1_000,
+            rand_pcg::Pcg32::seed_from_u64(1),
+            History::new(10, 10),
+        ));
+        let now = Instant::now();
+        assert!(shared.readiness(now).ready);
+        let stalled = shared.readiness(now + Duration::from_secs(6));
+        assert!(!stalled.ready);
+        assert_eq!(stalled.last_tick_at_ms, None);
+
+        shared
+            .metrics
+            .record_tick(Duration::from_millis(1), Duration::from_millis(0));
+        let ready = shared.readiness(Instant::now() + Duration::from_secs(4));
+        assert!(ready.ready);
+        assert!(ready.last_tick_at_ms.is_some());
+
+        // A panic while the world is locked poisons it
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            shared.update(|_| panic!("tick failed"));
+        }));
+        let poisoned = shared.readiness(Instant::now());
+        assert_eq!(
+            poisoned.problem,
+            Some(String::from("The world's lock is poisoned"))
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::world::scenario::EntitySpec;
+use crate::world::World;
+
+// Population figures for one tick. Means are over living eaters, and missing
+// when there are none.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TickStats {
+    pub tick: u64,
+    pub food: usize,
+    pub eaters: usize,
+    // Eaters born and entities that died during the tick
+    pub births: usize,
+    pub deaths: usize,
+    pub mean_hunger: Option<f64>,
+    pub mean_age: Option<f64>,
+}
+
+impl TickStats {
+    // Counts, births and deaths as `World::population` has them, so they agree
+    // with `/stats`. Eaters removed or added by hand aren't deaths or births.
+    pub fn of(world: &World) -> TickStats {
+        let population = world.population();
+        let ages: Vec<f64> = world
+            .entity_specs()
+            .into_iter()
+            .filter_map(|(_, spec)| match spec {
+                EntitySpec::Eater { age, .. } => Some(age as f64),
+                _ => None,
+            })
+            .collect();
+        TickStats {
+            tick: population.tick,
+            food: population.count("food"),
+            eaters: population.count("eater"),
+            births: population.changes.births.get("eater").copied().unwrap_or(0),
+            deaths: population.changes.deaths.values().sum(),
+            mean_hunger: population.mean_desires.get("hunger").copied(),
+            mean_age: mean(&ages),
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StatsFormat {
+    Csv,
+    JsonLines,
+}
+
+impl StatsFormat {
+    // An explicit format wins over the output's extension
+    pub fn for_output(format: Option<StatsFormat>, output: Option<&Path>) -> StatsFormat {
+        let extension = output
+            .and_then(|path| path.extension())
+            .and_then(|extension| extension.to_str());
+        match (format, extension) {
+            (Some(format), _) => format,
+            (None, Some("jsonl")) => StatsFormat::JsonLines,
+            _ => StatsFormat::Csv,
+        }
+    }
+}
+
+// Something written a line at a time by `StatsWriter`
+pub trait Row: Serialize {
+    const CSV_HEADER: &'static str;
+
+    // Without the line ending
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()>;
+}
+
+// Missing values are left empty in CSV
+pub fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |v| v.to_string())
+}
+
+impl Row for TickStats {
+    const CSV_HEADER: &'static str = "tick,food,eaters,births,deaths,mean_hunger,mean_age";
+
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(
+            out,
+            "{},{},{},{},{},{},{}",
+            self.tick,
+            self.food,
+            self.eaters,
+            self.births,
+            self.deaths,
+            optional(self.mean_hunger),
+            optional(self.mean_age)
+        )
+    }
+}
+
+// Writes one line per row, CSV with a header or JSON Lines
+pub struct StatsWriter<W: Write> {
+    out: W,
+    format: StatsFormat,
+    wrote_header: bool,
+}
+
+impl<W: Write> StatsWriter<W> {
+    pub fn new(out: W, format: StatsFormat) -> StatsWriter<W> {
+        StatsWriter {
+            out,
+            format,
+            wrote_header: false,
+        }
+    }
+
+    pub fn write<R: Row>(&mut self, row: &R) -> io::Result<()> {
+        match self.format {
+            StatsFormat::Csv => {
+                if !self.wrote_header {
+                    writeln!(self.out, "{}", R::CSV_HEADER)?;
+                    self.wrote_header = true;
+                }
+                row.write_csv(&mut self.out)?;
+            }
+            StatsFormat::JsonLines => serde_json::to_writer(&mut self.out, row)?,
+        }
+        writeln!(self.out)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Position;
+
+    #[test]
+    fn test_counts_births_and_deaths() {
+        use rand_core::SeedableRng;
+
+        let mut world = World::new(5, 5);
+        let eater = |x, y, hunger, age| EntitySpec::Eater {
+            position: Position { x, y },
+            hunger,
+            hunger_threshold: 20,
+            age,
+            last_reproduced: age,
+        };
+        let removed = world.spawn_entity(&eater(0, 0, 10, 20)).unwrap();
+        // Old and fed enough to reproduce this tick
+        world.spawn_entity(&eater(2, 2, 10, 50)).unwrap();
+        // Starves this tick
+        world.spawn_entity(&eater(4, 0, 100, 20)).unwrap();
+        world
+            .spawn_entity(&EntitySpec::Food {
+                position: Position { x: 4, y: 4 },
+            })
+            .unwrap();
+        let before = TickStats::of(&world);
+        assert_eq!((before.eaters, before.births, before.deaths), (3, 0, 0));
+        assert_eq!(before.mean_age, Some(30.0));
+
+        // Removing an eater isn't a death
+        world.remove_entity(removed).unwrap();
+        world.update(&mut rand_pcg::Pcg32::seed_from_u64(1));
+        let after = TickStats::of(&world);
+        let population = world.population();
+        assert_eq!(after.tick, 1);
+        assert_eq!((after.food, after.eaters), (1, 2));
+        assert_eq!((after.births, after.deaths), (1, 1));
+        assert_eq!(after.mean_hunger, Some(population.mean_desires["hunger"]));
+    }
+
+    #[test]
+    fn test_write_csv_and_json_lines() {
+        let stats = TickStats {
+            tick: 3,
+            food: 2,
+            eaters: 0,
+            births: 0,
+            deaths: 1,
+            mean_hunger: None,
+            mean_age: None,
+        };
+        let mut csv = StatsWriter::new(vec![], StatsFormat::Csv);
+        csv.write(&stats).unwrap();
+        csv.write(&stats).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.out).unwrap(),
+            format!("{}\n3,2,0,0,1,,\n3,2,0,0,1,,\n", TickStats::CSV_HEADER)
+        );
+
+        let mut json_lines = StatsWriter::new(vec![], StatsFormat::JsonLines);
+        json_lines.write(&stats).unwrap();
+        assert_eq!(
+            String::from_utf8(json_lines.out).unwrap(),
+            "{\"tick\":3,\"food\":2,\"eaters\":0,\"births\":0,\"deaths\":1,\"mean_hunger\":null,\"mean_age\":null}\n"
+        );
+    }
+}
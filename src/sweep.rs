@@ -0,0 +1,458 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::thread;
+
+use serde::Serialize;
+
+use crate::config;
+use crate::headless::{self, SimError};
+use crate::stats::{optional, Row, StatsFormat, StatsWriter, TickStats};
+use crate::thread_pool::ThreadPool;
+use crate::world::scenario::{EntitySpec, Scenario};
+use crate::world::World;
+
+pub const USAGE: &str = "Usage: garden-sim sweep [OPTIONS]
+
+Runs every combination of the given parameters against a scenario, in
+parallel, and writes one row of outcomes per combination.
+
+Parameters take comma separated values or inclusive ranges, like
+`5,10,20` or `10..30:5` (the step defaults to 1). Parameters that aren't
+given are left as they are in the scenario.
+
+Options:
+    --scenario <PATH>          Scenario to vary (default: the default world)
+    --food-spawn-every <LIST>  Ticks between food spawns
+    --hunger-threshold <LIST>  Hunger at which eaters go looking for food (0-100)
+    --size <LIST>              World sizes like 20x20,40x30; entities that no
+                               longer fit are dropped
+    --seed <LIST>              Seeds for the world's randomizer (default: none,
+                               the same randomizer as a single run)
+    --ticks <N>                Ticks to run each combination for (default 1000)
+    --jobs <N>                 Combinations run at once (default: one per core)
+    --format <csv|jsonl>       Output format (default: from the output's extension, else csv)
+    --output <PATH>            File to write the results to (default: stdout)
+
+A sweep runs at most 100000 combinations.";
+
+const DEFAULT_TICKS: u64 = 1000;
+// Beyond this a sweep is more likely a typo than a plan
+pub const MAX_COMBINATIONS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepOptions {
+    pub scenario: Option<PathBuf>,
+    pub ticks: u64,
+    // `None` keeps the scenario's own value
+    pub food_spawn_every: Vec<Option<i32>>,
+    pub hunger_threshold: Vec<Option<i8>>,
+    pub size: Vec<Option<(i32, i32)>>,
+    pub seed: Vec<Option<u64>>,
+    pub jobs: usize,
+    pub format: StatsFormat,
+    pub output: Option<PathBuf>,
+}
+
+// One combination of parameters
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct Parameters {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub food_spawn_every: Option<i32>,
+    pub hunger_threshold: Option<i8>,
+    pub seed: Option<u64>,
+}
+
+// What happened to the eaters over a run
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct Outcome {
+    // First tick without any eaters
+    pub extinction_tick: Option<u64>,
+    // Mean eaters over the last quarter of the run
+    pub steady_state_eaters: f64,
+    pub peak_eaters: usize,
+    pub final_eaters: usize,
+    pub births: usize,
+    pub deaths: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SweepResult {
+    #[serde(flatten)]
+    pub parameters: Parameters,
+    #[serde(flatten)]
+    pub outcome: Option<Outcome>,
+    // Set instead of the outcome when the combination couldn't run
+    pub error: Option<String>,
+}
+
+impl SweepOptions {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<SweepOptions, SimError> {
+        let mut options = SweepOptions {
+            scenario: None,
+            ticks: DEFAULT_TICKS,
+            food_spawn_every: vec![None],
+            hunger_threshold: vec![None],
+            size: vec![None],
+            seed: vec![None],
+            jobs: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            format: StatsFormat::Csv,
+            output: None,
+        };
+        let mut format = None;
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(SimError::HelpRequested);
+            }
+            let value = match flag.as_str() {
+                "--scenario" | "--food-spawn-every" | "--hunger-threshold" | "--size"
+                | "--seed" | "--ticks" | "--jobs" | "--format" | "--output" => args
+                    .next()
+                    .ok_or_else(|| SimError::MissingValue(flag.clone()))?,
+                _ => return Err(SimError::UnknownFlag(flag)),
+            };
+            match flag.as_str() {
+                "--scenario" => options.scenario = Some(PathBuf::from(value)),
+                "--food-spawn-every" => options.food_spawn_every = some(parse_list(&flag, &value)?),
+                "--hunger-threshold" => options.hunger_threshold = some(parse_list(&flag, &value)?),
+                "--size" => options.size = some(parse_sizes(&flag, &value)?),
+                "--seed" => options.seed = some(parse_list(&flag, &value)?),
+                "--ticks" => options.ticks = headless::parse_value(&flag, &value)?,
+                "--jobs" => {
+                    options.jobs = headless::parse_value(&flag, &value)?;
+                    if options.jobs == 0 {
+                        return Err(headless::invalid_value(&flag, &value));
+                    }
+                }
+                "--format" => format = Some(headless::parse_format(&flag, &value)?),
+                "--output" => options.output = Some(PathBuf::from(value)),
+                _ => unreachable!(),
+            }
+        }
+        options.format = StatsFormat::for_output(format, options.output.as_deref());
+        let combinations = [
+            options.size.len(),
+            options.food_spawn_every.len(),
+            options.hunger_threshold.len(),
+            options.seed.len(),
+        ]
+        .iter()
+        .try_fold(1usize, |total, count| total.checked_mul(*count));
+        match combinations {
+            Some(combinations) if combinations <= MAX_COMBINATIONS => Ok(options),
+            _ => Err(SimError::TooManyCombinations),
+        }
+    }
+
+    // Every combination, varying the seed fastest
+    pub fn combinations(&self) -> Vec<Parameters> {
+        let mut combinations = vec![];
+        for size in self.size.iter() {
+            for food_spawn_every in self.food_spawn_every.iter() {
+                for hunger_threshold in self.hunger_threshold.iter() {
+                    for seed in self.seed.iter() {
+                        combinations.push(Parameters {
+                            width: size.map(|(width, _)| width),
+                            height: size.map(|(_, height)| height),
+                            food_spawn_every: *food_spawn_every,
+                            hunger_threshold: *hunger_threshold,
+                            seed: *seed,
+                        });
+                    }
+                }
+            }
+        }
+        combinations
+    }
+}
+
+impl Parameters {
+    // The scenario with these parameters in place of its own
+    pub fn apply(&self, scenario: &Scenario) -> Result<Scenario, String> {
+        let mut scenario = scenario.clone();
+        scenario.width = self.width.unwrap_or(scenario.width);
+        scenario.height = self.height.unwrap_or(scenario.height);
+        let (width, height) = (scenario.width, scenario.height);
+        scenario.entities.retain(|entity| {
+            entity
+                .position()
+                .is_none_or(|p| p.x < width && p.y < height)
+        });
+        for entity in scenario.entities.iter_mut() {
+            match entity {
+                EntitySpec::FoodSpawner {
+                    spawn_every_x_ticks,
+                    ..
+                } => *spawn_every_x_ticks = self.food_spawn_every.unwrap_or(*spawn_every_x_ticks),
+                EntitySpec::Eater {
+                    hunger_threshold, ..
+                }
+                | EntitySpec::EaterSpawner {
+                    eater_hunger_threshold: hunger_threshold,
+                    ..
+                } => *hunger_threshold = self.hunger_threshold.unwrap_or(*hunger_threshold),
+                EntitySpec::Food { .. } => (),
+            }
+        }
+        scenario.validate().map_err(|e| e.to_string())?;
+        Ok(scenario)
+    }
+}
+
+// Runs all the combinations and writes a row for each, in the same order as
+// `SweepOptions::combinations` whichever finishes first
+pub fn run(options: &SweepOptions) -> Result<(), SimError> {
+    let scenario = match &options.scenario {
+        Some(path) => Scenario::load(path).map_err(SimError::Scenario)?,
+        None => Scenario::default(),
+    };
+    let out: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(File::create(path).map_err(SimError::Output)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = StatsWriter::new(BufWriter::new(out), options.format);
+    let results = sweep(
+        &scenario,
+        &options.combinations(),
+        options.ticks,
+        options.jobs,
+    );
+    results
+        .iter()
+        .try_for_each(|result| writer.write(result))
+        .and_then(|()| writer.flush())
+        .map_err(SimError::Output)
+}
+
+pub fn sweep(
+    scenario: &Scenario,
+    combinations: &[Parameters],
+    ticks: u64,
+    jobs: usize,
+) -> Vec<SweepResult> {
+    let pool = ThreadPool::new(jobs);
+    let handles: Vec<_> = combinations
+        .iter()
+        .map(|parameters| {
+            let parameters = *parameters;
+            let scenario = parameters.apply(scenario);
+            pool.submit(move || scenario.map(|scenario| run_one(&scenario, parameters.seed, ticks)))
+        })
+        .collect();
+    combinations
+        .iter()
+        .zip(handles)
+        .map(|(parameters, handle)| {
+            let result = handle.join().map_err(|e| e.to_string()).and_then(|r| r);
+            SweepResult {
+                parameters: *parameters,
+                error: result.as_ref().err().cloned(),
+                outcome: result.ok(),
+            }
+        })
+        .collect()
+}
+
+// Each run updates its world on a single thread, the sweep is parallel
+// across runs instead
+fn run_one(scenario: &Scenario, seed: Option<u64>, ticks: u64) -> Outcome {
+    let mut world: World = scenario.build_world();
+    let mut randomizer = config::randomizer(seed);
+    let steady_from = ticks - ticks / 4;
+    let mut steady_state = vec![];
+    let mut outcome = Outcome::default();
+    for tick in 0..=ticks {
+        if tick > 0 {
+            world.update(&mut randomizer);
+        }
+        let stats = TickStats::of(&world);
+        if stats.eaters == 0 && outcome.extinction_tick.is_none() {
+            outcome.extinction_tick = Some(tick);
+        }
+        if tick >= steady_from {
+            steady_state.push(stats.eaters as f64);
+        }
+        outcome.peak_eaters = outcome.peak_eaters.max(stats.eaters);
+        outcome.final_eaters = stats.eaters;
+        outcome.births += stats.births;
+        outcome.deaths += stats.deaths;
+    }
+    outcome.steady_state_eaters = steady_state.iter().sum::<f64>() / steady_state.len() as f64;
+    outcome
+}
+
+impl Row for SweepResult {
+    const CSV_HEADER: &'static str = "width,height,food_spawn_every,hunger_threshold,seed,\
+extinction_tick,steady_state_eaters,peak_eaters,final_eaters,births,deaths,error";
+
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // Parameters left as they were in the scenario are empty
+        let p = &self.parameters;
+        write!(
+            out,
+            "{},{},{},{},{},",
+            optional(p.width),
+            optional(p.height),
+            optional(p.food_spawn_every),
+            optional(p.hunger_threshold),
+            optional(p.seed)
+        )?;
+        match &self.outcome {
+            Some(o) => write!(
+                out,
+                "{},{},{},{},{},{},",
+                optional(o.extinction_tick),
+                o.steady_state_eaters,
+                o.peak_eaters,
+                o.final_eaters,
+                o.births,
+                o.deaths
+            )?,
+            None => write!(out, ",,,,,,")?,
+        }
+        // Errors are free text, quote them
+        match &self.error {
+            Some(error) => write!(out, "\"{}\"", error.replace('"', "\"\"")),
+            None => Ok(()),
+        }
+    }
+}
+
+fn some<T>(values: Vec<T>) -> Vec<Option<T>> {
+    values.into_iter().map(Some).collect()
+}
+
+// `5,10,20` or `10..30:5`, or a mix like `1,10..30:10`
+fn parse_list<T: TryFrom<i64>>(name: &str, value: &str) -> Result<Vec<T>, SimError> {
+    let invalid = || headless::invalid_value(name, value);
+    let mut values = vec![];
+    for part in value.split(',') {
+        let (range, step) = match part.split_once(':') {
+            Some((range, step)) => (range, headless::parse_value::<i64>(name, step)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (
+                headless::parse_value::<i64>(name, start)?,
+                headless::parse_value::<i64>(name, end)?,
+            ),
+            None => {
+                let single = headless::parse_value::<i64>(name, range)?;
+                (single, single)
+            }
+        };
+        if step <= 0 || start > end {
+            return Err(invalid());
+        }
+        // Counted before anything is expanded, so a huge range fails fast
+        let count = (end as i128 - start as i128) / step as i128 + 1;
+        if values.len() as i128 + count > MAX_COMBINATIONS as i128 {
+            return Err(SimError::TooManyCombinations);
+        }
+        let mut current = Some(start);
+        while let Some(value) = current.filter(|value| *value <= end) {
+            values.push(T::try_from(value).map_err(|_| invalid())?);
+            current = value.checked_add(step);
+        }
+    }
+    Ok(values)
+}
+
+// `20x20,40x30`, a single number is a square
+fn parse_sizes(name: &str, value: &str) -> Result<Vec<(i32, i32)>, SimError> {
+    value
+        .split(',')
+        .map(|size| match size.split_once('x') {
+            Some((width, height)) => Ok((
+                headless::parse_value(name, width)?,
+                headless::parse_value(name, height)?,
+            )),
+            None => {
+                let side = headless::parse_value(name, size)?;
+                Ok((side, side))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lists_and_ranges() {
+        assert_eq!(parse_list::<i32>("n", "5,10,20").unwrap(), vec![5, 10, 20]);
+        assert_eq!(
+            parse_list::<i32>("n", "1,10..30:10").unwrap(),
+            vec![1, 10, 20, 30]
+        );
+        assert_eq!(parse_list::<u64>("n", "3..5").unwrap(), vec![3, 4, 5]);
+        assert!(parse_list::<i8>("n", "100..200:50").is_err());
+        assert!(parse_list::<i32>("n", "5..1").is_err());
+        assert!(parse_list::<i32>("n", "1..5:0").is_err());
+        assert_eq!(
+            parse_list::<i64>("n", "9223372036854775806..9223372036854775807:5").unwrap(),
+            vec![i64::MAX - 1]
+        );
+        assert!(matches!(
+            parse_list::<u64>("n", "0..10000000000"),
+            Err(SimError::TooManyCombinations)
+        ));
+        assert_eq!(parse_sizes("n", "20x10,5").unwrap(), vec![(20, 10), (5, 5)]);
+    }
+
+    #[test]
+    fn test_sweep_every_combination() {
+        let options = SweepOptions::from_args(
+            [
+                "--size",
+                "10,30x30",
+                "--hunger-threshold",
+                "10,110",
+                "--seed",
+                "1..2",
+            ]
+            .iter()
+            .map(|arg| String::from(*arg)),
+        )
+        .unwrap();
+        let combinations = options.combinations();
+        assert_eq!(combinations.len(), 8);
+        assert_eq!(
+            combinations[1],
+            Parameters {
+                width: Some(10),
+                height: Some(10),
+                food_spawn_every: None,
+                hunger_threshold: Some(10),
+                seed: Some(2),
+            }
+        );
+
+        let too_many = ["--seed", "1..1000", "--food-spawn-every", "1..1000"];
+        assert!(matches!(
+            SweepOptions::from_args(too_many.iter().map(|arg| String::from(*arg))),
+            Err(SimError::TooManyCombinations)
+        ));
+
+        let results = sweep(&Scenario::default(), &combinations, 50, 4);
+        assert_eq!(
+            results.iter().map(|r| r.parameters).collect::<Vec<_>>(),
+            combinations
+        );
+        // Thresholds over 100 aren't valid, the rest all run
+        for result in results.iter() {
+            assert_eq!(
+                result.error.is_some(),
+                result.parameters.hunger_threshold == Some(110)
+            );
+        }
+        // Each run is reproducible from its seed
+        assert_eq!(
+            sweep(&Scenario::default(), &combinations[..2], 50, 1),
+            results[..2]
+        );
+    }
+}
@@ -1,16 +1,78 @@
-use std::thread;
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::thread;
 
-#[allow(dead_code)]
 pub struct ThreadPool{
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    workers: Mutex<Vec<Worker>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+    // Dropped first on shutdown so idle workers see the channel close
+    sender: Option<mpsc::Sender<Job>>,
+    counters: Arc<Counters>,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    panicked: AtomicUsize,
+}
+
+/// A point-in-time view of what the pool is doing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PoolStats {
+    pub size: usize,
+    pub queued_jobs: usize,
+    pub busy_workers: usize,
+    pub panicked_jobs: usize,
+}
+
+/// Reads a pool's stats from anywhere, without holding on to the pool.
+#[derive(Clone)]
+pub struct PoolMonitor {
+    size: usize,
+    counters: Arc<Counters>,
+}
+
+impl PoolMonitor {
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.size,
+            queued_jobs: self.counters.queued.load(Ordering::SeqCst),
+            busy_workers: self.counters.busy.load(Ordering::SeqCst),
+            panicked_jobs: self.counters.panicked.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Receives the result of a job passed to `ThreadPool::submit`.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<Result<T, JobError>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JobError {
+    /// The job panicked, holds the panic message.
+    Panicked(String),
+    /// The pool shut down before the job ran.
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(message) => write!(f, "Job panicked: {}", message),
+            JobError::Cancelled => write!(f, "Job was cancelled"),
+        }
+    }
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -25,42 +87,193 @@ impl ThreadPool {
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(Counters::default());
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&counters)));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool {
+            workers: Mutex::new(workers),
+            receiver,
+            sender: Some(sender),
+            counters,
+        }
     }
 
+    /// Run a job without waiting for it. Panics are logged and otherwise ignored.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        // Nobody is waiting on the result, dropping the handle is fine
+        let _ = self.submit(f);
+    }
+
+    /// Run a job and get a handle to its result.
+    ///
+    /// A panicking job doesn't take its worker down with it; the panic is
+    /// returned through the handle instead.
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.respawn_dead_workers();
+
+        let (result_sender, receiver) = mpsc::channel();
+        let counters = Arc::clone(&self.counters);
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+                counters.panicked.fetch_add(1, Ordering::SeqCst);
+                let message = panic_message(&*payload);
+                log::error!("Job panicked: {}", message);
+                JobError::Panicked(message)
+            });
+            // The caller may have dropped the handle, that's their business
+            let _ = result_sender.send(result);
+        });
+
+        self.counters.queued.fetch_add(1, Ordering::SeqCst);
+        self.sender.as_ref().unwrap().send(job).unwrap();
+        JobHandle { receiver }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.monitor().stats()
+    }
+
+    /// Dead workers are replaced, so the pool's size never changes.
+    pub fn monitor(&self) -> PoolMonitor {
+        PoolMonitor {
+            size: self.workers.lock().unwrap().len(),
+            counters: Arc::clone(&self.counters),
+        }
+    }
+
+    // Jobs catch their own panics, so a worker only dies if something outside
+    // of a job goes wrong. Replace it rather than slowly losing capacity.
+    fn respawn_dead_workers(&self) {
+        let mut workers = self.workers.lock().unwrap();
+        for worker in workers.iter_mut() {
+            if worker.thread.is_finished() {
+                log::warn!("Worker {} died; respawning.", worker.id);
+                let replacement = Worker::new(
+                    worker.id,
+                    Arc::clone(&self.receiver),
+                    Arc::clone(&self.counters),
+                );
+                let dead_worker = std::mem::replace(worker, replacement);
+                let _ = dead_worker.thread.join();
+            }
+        }
+    }
+}
 
-        self.sender.send(job).unwrap();
+impl Drop for ThreadPool {
+    // Waits for every queued job to finish
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in self.workers.get_mut().unwrap().drain(..) {
+            if worker.thread.join().is_err() {
+                log::error!("Worker {} panicked before shutting down.", worker.id);
+            }
+        }
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Block until the job has run.
+    pub fn join(self) -> Result<T, JobError> {
+        self.receiver.recv().unwrap_or(Err(JobError::Cancelled))
+    }
+
+    /// Return the result if the job has finished, without blocking.
+    pub fn try_join(&self) -> Option<Result<T, JobError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(JobError::Cancelled)),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
     }
 }
 
-#[allow(dead_code)]
 pub struct Worker {
     id: usize,
     thread: thread::JoinHandle<()>,
 }
 
 impl Worker {
-    pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker{
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, counters: Arc<Counters>) -> Worker{
         let thread = thread::spawn(move || loop{
-            let job = receiver.lock().unwrap().recv().unwrap();
+            let message = receiver.lock().unwrap().recv();
+
+            match message {
+                Ok(job) => {
+                    counters.queued.fetch_sub(1, Ordering::SeqCst);
+                    counters.busy.fetch_add(1, Ordering::SeqCst);
+                    log::debug!("Worker {} got a job; executing.", id);
 
-            println!("Worker {} got a job; executing.", id);
+                    job();
 
-            job();
+                    counters.busy.fetch_sub(1, Ordering::SeqCst);
+                }
+                // The pool has been dropped
+                Err(_) => break,
+            }
         });
 
         Worker{ id, thread }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_finishes_queued_jobs() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPool::new(2);
+            for _ in 0..8 {
+                let finished = Arc::clone(&finished);
+                pool.execute(move || {
+                    thread::sleep(std::time::Duration::from_millis(10));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        }
+        assert_eq!(finished.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_submit_returns_results_and_survives_panics() {
+        let pool = ThreadPool::new(1);
+
+        let panicked = pool.submit(|| -> i32 { panic!("oh no") });
+        assert_eq!(panicked.join(), Err(JobError::Panicked(String::from("oh no"))));
+
+        // The only worker is still around to run this
+        let answer = pool.submit(|| 6 * 7);
+        assert_eq!(answer.join(), Ok(42));
+
+        let stats = pool.stats();
+        assert_eq!(stats.size, 1);
+        assert_eq!(stats.panicked_jobs, 1);
+        assert_eq!(stats.queued_jobs, 0);
+    }
+}
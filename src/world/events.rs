@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use super::population::DeathCause;
+use super::{EntityId, Position};
+
+// Something that happened to an entity during a tick. Changes made from
+// outside the simulation, through inputs, aren't events.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WorldEvent {
+    Spawned {
+        id: EntityId,
+        kind: String,
+        position: Position,
+    },
+    // Food that's eaten goes with an `Ate` instead
+    Died {
+        id: EntityId,
+        cause: DeathCause,
+    },
+    Ate {
+        eater: EntityId,
+        food: EntityId,
+    },
+    // Follows the child's `Spawned`
+    Reproduced {
+        parent: EntityId,
+        child: EntityId,
+    },
+    Moved {
+        id: EntityId,
+        from: Position,
+        to: Position,
+    },
+}
@@ -12,10 +12,30 @@ const NEIGHBOR_DIRECTIONS: [(i32, i32); 4] = [
 pub fn a_star_pathfind(
     cur_pos: &Position,
     goal: &Position,
-    ignored_position: &Vec<Position>,
+    ignored_position: &[Position],
     world: &World,
 ) -> (i32, Position) {
-    let result = astar(
+    match a_star_path(cur_pos, goal, ignored_position, world) {
+        Some((p, c)) => {
+            // If we're somehow already standing on the object, return pretend its a square away
+            // This shouldn't happen though, fix it
+            if p.len() == 1 {
+                return (1, p[0]);
+            }
+            (c, p[1])
+        }
+        None => panic!("No path to goal found"),
+    }
+}
+
+// The whole path, starting with `cur_pos`, and its cost
+pub fn a_star_path(
+    cur_pos: &Position,
+    goal: &Position,
+    ignored_position: &[Position],
+    world: &World,
+) -> Option<(Vec<Position>, i32)> {
+    astar(
         cur_pos,
         // Create list of all position nighbors (giving cost 1 to all)
         |p| {
@@ -28,8 +48,8 @@ pub fn a_star_pathfind(
                     && 0 <= neighbor_y
                     && neighbor_y < *world.get_height()
                 {
-                    for i in 0..ignored_position.len() {
-                        if ignored_position[i].x == neighbor_x && ignored_position[i].y == neighbor_y {
+                    for ignored in ignored_position.iter() {
+                        if ignored.x == neighbor_x && ignored.y == neighbor_y {
                             continue 'neighbor_loop;
                         }
                     }
@@ -48,18 +68,7 @@ pub fn a_star_pathfind(
         |p| ((p.x - goal.x).abs() + (p.y - goal.y).abs()) / 3,
         // Check if (p)osition is goal
         |p| p == goal,
-    );
-    match result {
-        Some((p, c)) => {
-            // If we're somehow already standing on the object, return pretend its a square away
-            // This shouldn't happen though, fix it
-            if p.len() == 1 {
-                return (1, p[0]);
-            }
-            return (c, p[1]);
-        }
-        None => panic!("No path to goal found"),
-    }
+    )
 }
 
 #[test]
@@ -68,7 +77,7 @@ fn test_default() {
     let cur_pos = &Position{x: 0, y: 0};
     let goal_pos = &Position{x: 1, y: 1};
     
-    let (_, next_pos) = a_star_pathfind(cur_pos, goal_pos, &vec![], world);
+    let (_, next_pos) = a_star_pathfind(cur_pos, goal_pos, &[], world);
     
     let expected_pos = Position{x:1, y:0};
     assert_eq!(next_pos, expected_pos);
@@ -79,7 +88,7 @@ fn test_ignored_position() {
     let world = &World::new(2, 2);
     let cur_pos = &Position{x: 0, y: 0};
     let goal_pos = &Position{x: 1, y: 1};
-    let ignored_position = &vec![Position{x:1, y:0}];
+    let ignored_position = &[Position{x:1, y:0}];
     
     let (_, next_pos) = a_star_pathfind(cur_pos, goal_pos, ignored_position, world);
     
@@ -1,5 +1,7 @@
-use std::collections::HashMap;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
 
 use rand::distributions::{Distribution, Standard};
 use rand::seq::SliceRandom;
@@ -11,16 +13,34 @@ use log;
 
 use serde::{Deserialize, Serialize};
 
+use crate::logging;
+use crate::thread_pool::{JobError, ThreadPool};
+
+pub mod events;
 mod garden_pathfinding;
+pub mod population;
+pub mod scenario;
+
+use events::WorldEvent;
+use population::{DeathCause, PopulationChanges, PopulationStats};
 
+pub use eater::DEFAULT_HUNGER_THRESHOLD;
+
+// Cloning a world is cheap, the entities are shared until one side changes them
+#[derive(Clone)]
 pub struct World {
     pub width: i32,
     pub height: i32,
     // Sync and Send are required to ensure entities are thread-safe
-    entities: Vec<EntityType>,
-    removed_entity_indices: Vec<usize>,
+    entities: Arc<Vec<EntityType>>,
+    // Ids of `entities`, index for index
+    ids: Arc<Vec<EntityId>>,
+    next_id: EntityId,
     active: bool,
-    manual_update_requested: bool,
+    // Ticks run since the world was created
+    tick: u64,
+    // What happened during the last tick, in entity order
+    events: Arc<Vec<WorldEvent>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Copy, Clone, Hash)]
@@ -62,8 +82,126 @@ pub struct RenderedEntity {
     pub color: String,
 }
 
+// Identifies an entity for as long as it lives. Ids are never reused within a
+// world, but aren't kept in snapshots.
+pub type EntityId = u64;
+
+// An entity's full state, for inspecting it from outside the simulation
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EntityState {
+    pub id: EntityId,
+    #[serde(flatten)]
+    pub spec: scenario::EntitySpec,
+    #[serde(flatten)]
+    pub details: EntityDetails,
+}
+
+// What an entity is thinking, on top of what it takes to recreate it
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct EntityDetails {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub desires: BTreeMap<String, DesireLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub goal: Option<String>,
+    // Cells still to cross to reach the goal
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub path: Vec<Position>,
+    // The entity the goal is about, e.g. the food an eater is after
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Target {
+    pub id: EntityId,
+    pub position: Position,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DesireLevel {
+    pub level: i8,
+    // Acts on the desire once the level reaches this
+    pub threshold: i8,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EntityError {
+    NotFound(EntityId),
+    Occupied(Position),
+    Invalid(String),
+}
+
+impl fmt::Display for EntityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityError::NotFound(id) => write!(f, "No entity with id {}", id),
+            EntityError::Occupied(position) => {
+                write!(f, "Cell ({}, {}) is occupied", position.x, position.y)
+            }
+            EntityError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<scenario::ScenarioError> for EntityError {
+    fn from(error: scenario::ScenarioError) -> EntityError {
+        match error {
+            scenario::ScenarioError::Invalid(reason) => EntityError::Invalid(reason),
+            other => EntityError::Invalid(other.to_string()),
+        }
+    }
+}
+
 type EntityType = Box<dyn Updateable + Sync + Send>;
 
+// Lets boxed entities be cloned without every entity implementing it by hand
+pub trait CloneEntity {
+    fn clone_entity(&self) -> EntityType;
+}
+
+impl<T> CloneEntity for T
+where
+    T: Updateable + Clone + Sync + Send + 'static,
+{
+    fn clone_entity(&self) -> EntityType {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for EntityType {
+    fn clone(&self) -> Self {
+        self.clone_entity()
+    }
+}
+
+// What an entity wants to do this tick, decided against the world as it was
+// at the start of the tick. Intents can conflict (two eaters after the same
+// food, two entities moving into the same cell), `World::resolve` decides who wins.
+#[derive(Default)]
+pub struct Intent {
+    // The entity's state if everything goes to plan, None if it dies
+    pub next: Option<EntityType>,
+    // The entity's state if its move, meal or spawn loses out, defaults to `next`
+    pub fallback: Option<EntityType>,
+    pub spawn: Option<EntityType>,
+    // Index of the entity to eat
+    pub eat: Option<usize>,
+    // Why the entity died, when `next` is None
+    pub died_of: Option<DeathCause>,
+}
+
+impl Intent {
+    fn stay(next: EntityType) -> Intent {
+        Intent {
+            next: Some(next),
+            ..Intent::default()
+        }
+    }
+}
+
+// Entities per planning job; below this it isn't worth leaving the tick thread
+const MIN_ENTITIES_PER_JOB: usize = 64;
+
 impl Debug for EntityType {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
@@ -75,52 +213,165 @@ impl Debug for EntityType {
     }
 }
 
+impl Default for World {
+    fn default() -> World {
+        scenario::Scenario::default().build_world()
+    }
+}
+
 impl World {
     pub fn new(width: i32, height: i32) -> World {
         World {
-            height: height,
-            width: width,
-            entities: vec![],
-            removed_entity_indices: vec![],
+            height,
+            width,
+            entities: Arc::new(vec![]),
+            ids: Arc::new(vec![]),
+            next_id: 0,
             active: true,
-            manual_update_requested: false,
+            tick: 0,
+            events: Arc::new(vec![]),
         }
     }
 
-    pub fn default() -> World {
-        let entities: Vec<EntityType> = vec![
-            Box::new(food_spawner::FoodSpawner::new(0, 10)),
-            Box::new(eater_spawner::EaterSpawner::new(0)),
-            Box::new(eater::Eater::new(Position {x: 15, y: 15})),
-            Box::new(food::Food::new(Position { x: 20, y: 20 })),
-        ];
+    pub fn get_height(&self) -> &i32 {
+        &self.height
+    }
+    pub fn get_width(&self) -> &i32 {
+        &self.width
+    }
+
+    pub fn current_tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
 
-        let width = 30;
-        let height = 30;
+    pub fn entity_count(&self) -> usize {
+        self.entities.len()
+    }
 
-        World {
-            width: width,
-            height: height,
-            entities: entities,
-            removed_entity_indices: vec![],
-            active: true,
-            manual_update_requested: false,
+    pub fn eater_count(&self) -> usize {
+        self.get_eater_entities().len()
+    }
+
+    // Counts and averages are taken now, births and deaths are the last tick's
+    pub fn population(&self) -> PopulationStats {
+        let mut counts = BTreeMap::new();
+        let mut desires: BTreeMap<String, (f64, usize)> = BTreeMap::new();
+        for entity in self.entities.iter() {
+            let kind = entity.to_spec().kind();
+            *counts.entry(String::from(kind)).or_insert(0) += 1;
+            for (desire, level) in entity.desires() {
+                let (total, count) = desires.entry(String::from(desire)).or_insert((0.0, 0));
+                *total += level as f64;
+                *count += 1;
+            }
+        }
+        PopulationStats {
+            tick: self.tick,
+            counts,
+            changes: PopulationChanges::from_events(&self.events),
+            mean_desires: desires
+                .into_iter()
+                .map(|(desire, (total, count))| (desire, total / count as f64))
+                .collect(),
         }
     }
 
-    pub fn get_height(&self) -> &i32 {
-        &self.height
+    // Shared, so handing the events on doesn't copy them
+    pub fn events(&self) -> Arc<Vec<WorldEvent>> {
+        Arc::clone(&self.events)
     }
-    pub fn get_width(&self) -> &i32 {
-        &self.width
+
+    pub fn add_entity(&mut self, entity: EntityType) -> EntityId {
+        let id = self.next_id;
+        self.next_id += 1;
+        Arc::make_mut(&mut self.entities).push(entity);
+        Arc::make_mut(&mut self.ids).push(id);
+        id
+    }
+
+    // Every entity with its id, in update order
+    pub fn entity_specs(&self) -> Vec<(EntityId, scenario::EntitySpec)> {
+        self.ids
+            .iter()
+            .zip(self.entities.iter())
+            .map(|(id, entity)| (*id, entity.to_spec()))
+            .collect()
+    }
+
+    pub fn entity_state(&self, id: EntityId) -> Option<EntityState> {
+        let entity = &self.entities[self.entity_index(id)?];
+        Some(EntityState {
+            id,
+            spec: entity.to_spec(),
+            details: entity.details(self),
+        })
+    }
+
+    // Unlike `add_entity`, checks the entity fits in the world and has a cell to itself
+    pub fn spawn_entity(&mut self, spec: &scenario::EntitySpec) -> Result<EntityId, EntityError> {
+        self.check_placement(spec, None)?;
+        Ok(self.add_entity(spec.build()))
     }
 
-    pub fn add_entity(&mut self, entity: EntityType) {
-        self.entities.push(entity);
+    pub fn remove_entity(&mut self, id: EntityId) -> Result<(), EntityError> {
+        let index = self.entity_index(id).ok_or(EntityError::NotFound(id))?;
+        Arc::make_mut(&mut self.entities).remove(index);
+        Arc::make_mut(&mut self.ids).remove(index);
+        Ok(())
+    }
+
+    // Replaces an entity with the same kind of entity, keeping its id
+    pub fn replace_entity(
+        &mut self,
+        id: EntityId,
+        spec: &scenario::EntitySpec,
+    ) -> Result<(), EntityError> {
+        let index = self.entity_index(id).ok_or(EntityError::NotFound(id))?;
+        let kind = self.entities[index].to_spec().kind();
+        if spec.kind() != kind {
+            return Err(EntityError::Invalid(format!(
+                "Entity {} is a {}, it can't become a {}",
+                id,
+                kind,
+                spec.kind()
+            )));
+        }
+        self.check_placement(spec, Some(index))?;
+        Arc::make_mut(&mut self.entities)[index] = spec.build();
+        Ok(())
+    }
+
+    fn entity_index(&self, id: EntityId) -> Option<usize> {
+        self.ids.iter().position(|entity_id| *entity_id == id)
+    }
+
+    // `ignored` is the index of an entity being replaced, which may stay where it is
+    fn check_placement(
+        &self,
+        spec: &scenario::EntitySpec,
+        ignored: Option<usize>,
+    ) -> Result<(), EntityError> {
+        spec.validate(self.width, self.height)?;
+        let position = match spec.position() {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let occupied = self
+            .entities
+            .iter()
+            .enumerate()
+            .any(|(i, entity)| Some(i) != ignored && *entity.get_position() == position);
+        if occupied {
+            return Err(EntityError::Occupied(position));
+        }
+        Ok(())
     }
 
     pub fn render(&self) -> Vec<RenderedEntity> {
-        let start = Instant::now();
         let mut rendered_entities = vec![];
         for entity in self.entities.iter() {
             rendered_entities.push(RenderedEntity {
@@ -128,18 +379,21 @@ impl World {
                 color: String::from(entity.get_color()),
             });
         }
-        let render_time = start.elapsed().as_millis() as u64;
-        log::info!("Time to render world: {}", render_time);
         rendered_entities
     }
 
+    pub fn snapshot(&self) -> scenario::Scenario {
+        scenario::Scenario {
+            width: self.width,
+            height: self.height,
+            tick: self.tick,
+            entities: self.entities.iter().map(|entity| entity.to_spec()).collect(),
+        }
+    }
+
     fn get_food_entities(&self) -> Vec<usize> {
         let mut food_entity_indices = vec![];
         for (i, entity) in self.entities.iter().enumerate() {
-            if self.removed_entity_indices.iter().any(|j| *j == i) {
-                // entity has been destroyed
-                continue;
-            }
             if entity.get_name() == "food" {
                 food_entity_indices.push(i);
             }
@@ -150,10 +404,6 @@ impl World {
     fn get_eater_entities(&self) -> Vec<usize> {
         let mut food_entity_indices = vec![];
         for (i, entity) in self.entities.iter().enumerate() {
-            if self.removed_entity_indices.iter().any(|j| *j == i) {
-                // entity has been destroyed
-                continue;
-            }
             if entity.get_name() == "eater" {
                 food_entity_indices.push(i);
             }
@@ -162,17 +412,9 @@ impl World {
     }
 
     fn get_entity_at(&self, position: &Position) -> Option<&EntityType> {
-        for i in 0..self.entities.len() {
-            let entity_position = self.entities[i].get_position();
-            if self.removed_entity_indices.iter().any(|j| *j == i) {
-                // entity has been destroyed
-                continue;
-            }
-            if *position == *entity_position {
-                return Some(&self.entities[i]);
-            }
-        }
-        None
+        self.entities
+            .iter()
+            .find(|entity| *entity.get_position() == *position)
     }
 
     fn get_new_position(&self, cur_position: &Position, direction: &Direction) -> Position {
@@ -240,50 +482,202 @@ impl World {
         new_position
     }
 
-    pub fn update_if_active(&mut self, randomizer: &mut rand_pcg::Pcg32){
-        if self.active {
-            self.update(randomizer);
-        } else if self.manual_update_requested {
-            self.manual_update_requested = false;
-            self.update(randomizer);
+    pub fn update_if_active(
+        &mut self,
+        randomizer: &mut rand_pcg::Pcg32,
+        pool: Option<&ThreadPool>,
+    ) -> bool {
+        self.active && self.tick(randomizer, pool)
+    }
+
+    // TODO: Generalize randomizer
+    pub fn update(&mut self, randomizer: &mut rand_pcg::Pcg32) {
+        self.tick(randomizer, None);
+    }
+
+    // Same result as `update` for the same randomizer, however many threads the pool has
+    pub fn update_parallel(&mut self, randomizer: &mut rand_pcg::Pcg32, pool: &ThreadPool) -> bool {
+        self.tick(randomizer, Some(pool))
+    }
+
+    // Returns whether the world moved on a tick. A skipped tick leaves the
+    // randomizer as it was, so a run stays reproducible from its seed.
+    fn tick(&mut self, randomizer: &mut rand_pcg::Pcg32, pool: Option<&ThreadPool>) -> bool {
+        // Each entity gets its own stream of this seed, so the numbers it draws
+        // don't depend on which thread planned it or in what order
+        let mut next_randomizer = randomizer.clone();
+        let seed = next_randomizer.gen::<u64>();
+        let update = logging::span("update");
+        let intents = match pool {
+            Some(pool) => self.plan_parallel(seed, pool),
+            None => Ok(self.plan(seed, 0..self.entities.len())),
+        };
+        drop(update);
+        match intents {
+            Ok(intents) => {
+                *randomizer = next_randomizer;
+                self.resolve(intents);
+                self.tick += 1;
+                true
+            }
+            // Nothing has been changed yet, so the world stays as it was
+            Err(e) => {
+                log::error!("Unable to plan entity updates, skipped the tick: {}", e);
+                false
+            }
         }
     }
 
-    pub fn request_manual_update(&mut self) {
-        self.manual_update_requested = true;
+    fn plan(&self, seed: u64, indices: Range<usize>) -> Vec<Intent> {
+        indices
+            .map(|i| {
+                let mut rng = rand_pcg::Pcg32::new(seed, i as u64);
+                self.entities[i].update(self, &mut rng)
+            })
+            .collect()
     }
 
-    // TODO: Generalize randomizer
-    pub fn update(&mut self, randomizer: &mut rand_pcg::Pcg32) {
+    fn plan_parallel(&self, seed: u64, pool: &ThreadPool) -> Result<Vec<Intent>, JobError> {
+        let entity_count = self.entities.len();
+        let threads = pool.stats().size;
+        let chunk_size = entity_count.div_ceil(threads).max(MIN_ENTITIES_PER_JOB);
+        if chunk_size >= entity_count {
+            return Ok(self.plan(seed, 0..entity_count));
+        }
+
+        let snapshot = Arc::new(self.clone());
+        // So what the jobs log still says which world and tick it's about
+        let fields = logging::current_fields();
+        let jobs: Vec<_> = (0..entity_count)
+            .step_by(chunk_size)
+            .map(|start| {
+                let snapshot = Arc::clone(&snapshot);
+                let fields = fields.clone();
+                let end = (start + chunk_size).min(entity_count);
+                pool.submit(move || {
+                    let _fields = fields.attach();
+                    snapshot.plan(seed, start..end)
+                })
+            })
+            .collect();
+        // Chunks are joined in order, so intents line up with entity indices
+        let mut intents = Vec::with_capacity(entity_count);
+        for job in jobs {
+            intents.extend(job.join()?);
+        }
+        Ok(intents)
+    }
+
+    // Applies intents in entity order, the first entity to claim a cell or a
+    // meal gets it. Cells held at the start of the tick stay taken until the
+    // next tick even if their entity moves away.
+    fn resolve(&mut self, intents: Vec<Intent>) {
+        // Covers moves and meals too, everything but spawning
+        let removal = logging::span("removal");
+        let mut events = vec![];
+        let mut eaten = vec![false; intents.len()];
+        let mut ate = vec![false; intents.len()];
+        for (i, intent) in intents.iter().enumerate() {
+            if let Some(food_idx) = intent.eat {
+                if food_idx != i && !eaten[food_idx] && !eaten[i] {
+                    eaten[food_idx] = true;
+                    ate[i] = true;
+                }
+            }
+        }
+
+        let mut occupied: HashSet<Position> = self
+            .entities
+            .iter()
+            .zip(intents.iter())
+            .enumerate()
+            .filter(|(i, (_, intent))| !eaten[*i] && intent.next.is_some())
+            .map(|(_, (entity, _))| *entity.get_position())
+            .collect();
+
+        let mut entities = Vec::with_capacity(intents.len());
+        let mut ids = Vec::with_capacity(intents.len());
         let mut spawned_entities = Vec::new();
-        for i in 0..self.entities.len() {
-            // May be worth maintaining a separate iterable of "active objects"
-            if self.removed_entity_indices.iter().any(|j| *j == i) {
-                // entity has been destroyed
+        for (i, intent) in intents.into_iter().enumerate() {
+            let id = self.ids[i];
+            if eaten[i] {
+                if self.entities[i].to_spec().kind() != "food" {
+                    let cause = DeathCause::Predation;
+                    events.push(WorldEvent::Died { id, cause });
+                }
                 continue;
             }
-
-            let (entity, spawned_entity, removed_entity_index) =
-                self.entities[i].update(&self, randomizer);
-
-            // Replace entity state with new state
-            self.entities[i] = entity;
-            if let Some(e) = spawned_entity {
-                // This needs to happen immediately, a push is safe
-                spawned_entities.push(e);
+            let next = match intent.next {
+                Some(next) => next,
+                // entity died
+                None => {
+                    if let Some(cause) = intent.died_of {
+                        events.push(WorldEvent::Died { id, cause });
+                    }
+                    continue;
+                }
+            };
+            if let (Some(food_idx), true) = (intent.eat, ate[i]) {
+                if self.entities[food_idx].to_spec().kind() == "food" {
+                    let food = self.ids[food_idx];
+                    events.push(WorldEvent::Ate { eater: id, food });
+                }
             }
-            if let Some(i) = removed_entity_index {
-                self.removed_entity_indices.push(i)
+
+            let position = *self.entities[i].get_position();
+            let moved = *next.get_position() != position;
+            let blocked = (intent.eat.is_some() && !ate[i])
+                || (moved && occupied.contains(next.get_position()))
+                || intent
+                    .spawn
+                    .as_ref()
+                    .is_some_and(|spawn| occupied.contains(spawn.get_position()));
+
+            let entity = if blocked {
+                intent.fallback.unwrap_or(next)
+            } else {
+                if let Some(spawn) = intent.spawn {
+                    occupied.insert(*spawn.get_position());
+                    spawned_entities.push((i, spawn));
+                }
+                next
+            };
+            if *entity.get_position() != position {
+                let to = *entity.get_position();
+                events.push(WorldEvent::Moved {
+                    id,
+                    from: position,
+                    to,
+                });
             }
+            occupied.insert(*entity.get_position());
+            entities.push(entity);
+            ids.push(id);
         }
-        // Must be ordered by descending in order for swap_remove to work
-        self.removed_entity_indices.sort();
-        self.removed_entity_indices.reverse();
-        for removal_index in self.removed_entity_indices.iter() {
-            self.entities.swap_remove(*removal_index as usize);
+        drop(removal);
+
+        // Spawned entities get ids in the order they were spawned, so replays
+        // hand out the same ids
+        let _spawn = logging::span("spawn");
+        for (parent_idx, spawn) in spawned_entities {
+            let child = self.next_id;
+            self.next_id += 1;
+            let kind = spawn.to_spec().kind();
+            events.push(WorldEvent::Spawned {
+                id: child,
+                kind: String::from(kind),
+                position: *spawn.get_position(),
+            });
+            if self.entities[parent_idx].to_spec().kind() == kind {
+                let parent = self.ids[parent_idx];
+                events.push(WorldEvent::Reproduced { parent, child });
+            }
+            entities.push(spawn);
+            ids.push(child);
         }
-        self.removed_entity_indices.clear();
-        self.entities.append(&mut spawned_entities);
+        self.entities = Arc::new(entities);
+        self.ids = Arc::new(ids);
+        self.events = Arc::new(events);
     }
 
     pub fn pause(&mut self) {
@@ -302,7 +696,7 @@ impl World {
                 let mut found_entity = false;
                 for entity in self.entities.iter() {
                     if x == entity.get_position().x && y == entity.get_position().y {
-                        line.push_str("🍓");
+                        line.push('🍓');
                         found_entity = true;
                     }
                 }
@@ -321,12 +715,19 @@ pub const BROWN: &str = "#996600";
 pub const BLACK: &str = "#000000";
 pub const GREEN: &str = "#009933";
 
-pub trait Updateable {
-    fn update(
-        &self,
-        world: &World,
-        rng: &mut rand_pcg::Pcg32,
-    ) -> (EntityType, Option<EntityType>, Option<usize>);
+// Order is part of the binary websocket format; append new colors to the end
+pub const PALETTE: [&str; 4] = [GREEN, RED, BROWN, BLACK];
+
+pub fn palette_index(color: &str) -> Option<u8> {
+    PALETTE
+        .iter()
+        .position(|c| *c == color)
+        .map(|index| index as u8)
+}
+
+pub trait Updateable: CloneEntity {
+    // Entities only see the world as it was at the start of the tick
+    fn update(&self, world: &World, rng: &mut rand_pcg::Pcg32) -> Intent;
 
     fn get_name(&self) -> &str {
         "unnamed"
@@ -339,10 +740,24 @@ pub trait Updateable {
     fn get_color(&self) -> &str {
         GREEN
     } // Hack to make appear invisible
+
+    // Everything needed to recreate this entity from a scenario
+    fn to_spec(&self) -> scenario::EntitySpec;
+
+    fn details(&self, _world: &World) -> EntityDetails {
+        EntityDetails::default()
+    }
+
+    // Current level of each desire, by name
+    fn desires(&self) -> Vec<(&'static str, i8)> {
+        vec![]
+    }
 }
 
 mod food_spawner {
     use super::*;
+    #[cfg(test)]
+    use rand_core::SeedableRng;
 
     #[derive(Debug, Clone, Copy)]
     pub struct FoodSpawner {
@@ -351,31 +766,37 @@ mod food_spawner {
     }
 
     impl Updateable for FoodSpawner {
-        fn update(
-            &self,
-            world: &World,
-            rng: &mut rand_pcg::Pcg32,
-        ) -> (EntityType, Option<EntityType>, Option<usize>) {
-            let mut new_spawner = self.clone();
+        fn update(&self, world: &World, rng: &mut rand_pcg::Pcg32) -> Intent {
+            let mut new_spawner = *self;
             if self.last_spawned + 1 >= self.spawn_every_x_ticks {
                 let x = rng.gen_range(0..world.width);
                 let y = rng.gen_range(0..world.height);
                 let spawn_position = Position { x, y };
                 let mut new_food: Option<EntityType> = None;
-                if let None = world.get_entity_at(&spawn_position) {
+                if world.get_entity_at(&spawn_position).is_none() {
                     new_food = Some(Box::new(food::Food::new(spawn_position)));
                 };
                 new_spawner.last_spawned = 0;
-                (Box::new(new_spawner), new_food, None)
+                Intent {
+                    spawn: new_food,
+                    ..Intent::stay(Box::new(new_spawner))
+                }
             } else {
                 new_spawner.last_spawned += 1;
-                (Box::new(new_spawner), None, None)
+                Intent::stay(Box::new(new_spawner))
             }
         }
 
         fn get_position(&self) -> &Position {
             &Position { x: 0, y: 0 }
         }
+
+        fn to_spec(&self) -> scenario::EntitySpec {
+            scenario::EntitySpec::FoodSpawner {
+                spawn_every_x_ticks: self.spawn_every_x_ticks,
+                last_spawned: self.last_spawned,
+            }
+        }
     }
 
     impl FoodSpawner {
@@ -389,12 +810,11 @@ mod food_spawner {
 
     #[test]
     fn test_food_spawner() {
-        let entities: Vec<EntityType> = vec![Box::new(food_spawner::FoodSpawner {
+        let mut world = World::new(10, 10);
+        world.add_entity(Box::new(food_spawner::FoodSpawner {
             last_spawned: 9,
             spawn_every_x_ticks: 10,
-        })];
-        let mut world = World::new(10, 10);
-        world.entities = entities;
+        }));
         let mut randomizer = rand_pcg::Pcg32::from_seed(*b"somebody once to");
         world.update(&mut randomizer);
         assert_eq!(world.entities.len(), 2);
@@ -414,13 +834,9 @@ mod food {
             "food"
         }
 
-        fn update(
-            &self,
-            _world: &World,
-            _rng: &mut rand_pcg::Pcg32,
-        ) -> (EntityType, Option<EntityType>, Option<usize>) {
-            let new_food = self.clone();
-            (Box::new(new_food), None, None)
+        fn update(&self, _world: &World, _rng: &mut rand_pcg::Pcg32) -> Intent {
+            let new_food = *self;
+            Intent::stay(Box::new(new_food))
         }
 
         fn get_position(&self) -> &Position {
@@ -430,11 +846,17 @@ mod food {
         fn get_color(&self) -> &str {
             RED
         }
+
+        fn to_spec(&self) -> scenario::EntitySpec {
+            scenario::EntitySpec::Food {
+                position: self.position,
+            }
+        }
     }
 
     impl Food {
         pub fn new(position: Position) -> Food {
-            Food { position: position }
+            Food { position }
         }
     }
 }
@@ -444,13 +866,24 @@ enum Desire {
     Hunger,
 }
 
+impl Desire {
+    fn name(&self) -> &'static str {
+        match self {
+            Desire::Hunger => "hunger",
+        }
+    }
+}
+
 mod eater_spawner {
     use super::*;
 
     const SPAWN_AFTER_X_TICKS: i32 = 20;
 
+    #[derive(Clone)]
     pub struct EaterSpawner {
         ticks_without_eater: i32,
+        // Passed on to the eaters it spawns
+        eater_hunger_threshold: i8,
     }
 
     impl Updateable for EaterSpawner{
@@ -458,14 +891,10 @@ mod eater_spawner {
             "eater spawner"
         }
 
-        fn update(
-            &self,
-            world: &World,
-            rand_gen: &mut rand_pcg::Pcg32,
-        ) -> (EntityType, Option<EntityType>, Option<usize>) {
+        fn update(&self, world: &World, rand_gen: &mut rand_pcg::Pcg32) -> Intent {
 
             let mut ticks_without_eater = self.ticks_without_eater;
-            if world.get_eater_entities().len() == 0 {
+            if world.get_eater_entities().is_empty() {
                 ticks_without_eater += 1
             }
             
@@ -474,24 +903,45 @@ mod eater_spawner {
                 let x = rand_gen.gen_range(0..world.width);
                 let y = rand_gen.gen_range(0..world.height);
                 let spawn_position = Position { x, y };
-                if let None = world.get_entity_at(&spawn_position) {
-                    created_eater = Some(Box::new(eater::Eater::new(spawn_position)));
+                if world.get_entity_at(&spawn_position).is_none() {
+                    let eater = eater::Eater::new(spawn_position)
+                        .with_hunger_threshold(self.eater_hunger_threshold);
+                    created_eater = Some(Box::new(eater));
                 };
                 ticks_without_eater = 0;
             }
 
-            let new_eater_spawner = Box::new(EaterSpawner::new(ticks_without_eater));
+            let new_eater_spawner = Box::new(EaterSpawner {
+                ticks_without_eater,
+                ..self.clone()
+            });
 
-            (new_eater_spawner, created_eater, None)
+            Intent {
+                spawn: created_eater,
+                ..Intent::stay(new_eater_spawner)
+            }
+        }
+
+        fn to_spec(&self) -> scenario::EntitySpec {
+            scenario::EntitySpec::EaterSpawner {
+                ticks_without_eater: self.ticks_without_eater,
+                eater_hunger_threshold: self.eater_hunger_threshold,
+            }
         }
     }
 
     impl EaterSpawner {
         pub fn new(ticks_without_eater: i32) -> EaterSpawner {
             EaterSpawner{
-                ticks_without_eater
+                ticks_without_eater,
+                eater_hunger_threshold: eater::DEFAULT_HUNGER_THRESHOLD,
             }
         }
+
+        pub fn with_eater_hunger_threshold(mut self, threshold: i8) -> EaterSpawner {
+            self.eater_hunger_threshold = threshold;
+            self
+        }
     }
 }
 
@@ -499,6 +949,9 @@ mod eater_spawner {
 mod eater {
     use super::*;
 
+    // Eaters go looking for food once they're this hungry
+    pub const DEFAULT_HUNGER_THRESHOLD: i8 = 20;
+
     #[derive(Clone)]
     pub struct Eater {
         position: Position,
@@ -512,26 +965,35 @@ mod eater {
     enum EaterGoal {
         GetFood(usize), // Approach or consume food entity
         Wander,         // Move randomly
-        Die,
+        Die(DeathCause),
         Reproduce,
     }
 
+    impl EaterGoal {
+        fn name(&self) -> &'static str {
+            match self {
+                EaterGoal::GetFood(_) => "get_food",
+                EaterGoal::Wander => "wander",
+                EaterGoal::Die(_) => "die",
+                EaterGoal::Reproduce => "reproduce",
+            }
+        }
+    }
+
     impl Updateable for Eater {
         fn get_name(&self) -> &str {
             "eater"
         }
 
-        fn update(
-            &self,
-            world: &World,
-            rand_gen: &mut rand_pcg::Pcg32,
-        ) -> (EntityType, Option<EntityType>, Option<usize>) {
+        fn update(&self, world: &World, rand_gen: &mut rand_pcg::Pcg32) -> Intent {
             let mut new_eater = self.clone();
 
             new_eater.increment_desire(Desire::Hunger, 1);
             new_eater.age += 1;
             new_eater.last_reproduced += 1;
-            let mut removed_entity_index = None;
+            // Losing a cell or a meal to another entity costs this one a tick
+            let waited: EntityType = Box::new(new_eater.clone());
+            let mut eaten_entity_index = None;
             let mut offspring: Option<EntityType> = None;
 
             let goal = self.select_goal(world);
@@ -540,12 +1002,12 @@ mod eater {
                     // Shuffle all positions
                     // If the entity is surrounded, it won't move at all
                     // I doubt this is much slower than choosing a single position but its worth profiling
-                    let mut move_attempts = CARDINAL_DIRECTIONS.clone();
+                    let mut move_attempts = CARDINAL_DIRECTIONS;
                     move_attempts.shuffle(rand_gen);
                     let mut next_position = self.position;
-                    for i in 0..move_attempts.len() {
-                        next_position = world.get_new_position(&self.position, &move_attempts[i]);
-                        if let Some(_) = world.get_entity_at(&next_position) {
+                    for direction in move_attempts.iter() {
+                        next_position = world.get_new_position(&self.position, direction);
+                        if world.get_entity_at(&next_position).is_some() {
                             continue;
                         }
                     }
@@ -568,12 +1030,12 @@ mod eater {
 
                         // Eater is adjacent to food (note: should only ever happen in first loop)
                         if cost == 1 {
-                            removed_entity_index = Some(food_idx);
+                            eaten_entity_index = Some(food_idx);
                             new_eater.increment_desire(Desire::Hunger, -20);
                             break;
                         }
 
-                        if let None = world.get_entity_at(&try_position){
+                        if world.get_entity_at(&try_position).is_none() {
                             next_position = try_position;
                             break;
                         }
@@ -582,35 +1044,42 @@ mod eater {
                     }
                     new_eater.position = next_position;
                 }
-                EaterGoal::Die => {
-                    let self_idx = world
-                        .entities
-                        .iter()
-                        .position(|cur_entity| *cur_entity.get_position() == self.position)
-                        .expect("entity not found in world vector of entities");
-                    removed_entity_index = Some(self_idx);
+                EaterGoal::Die(cause) => {
+                    return Intent {
+                        died_of: Some(cause),
+                        ..Intent::default()
+                    }
                 }
                 EaterGoal::Reproduce => {
 
-                    let mut move_attempts = CARDINAL_DIRECTIONS.clone();
+                    let mut move_attempts = CARDINAL_DIRECTIONS;
                     move_attempts.shuffle(rand_gen);
                     let mut next_position = self.position;
-                    for i in 0..move_attempts.len() {
-                        next_position = world.get_new_position(&self.position, &move_attempts[i]);
-                        if let Some(_) = world.get_entity_at(&next_position) {
+                    for direction in move_attempts.iter() {
+                        next_position = world.get_new_position(&self.position, direction);
+                        if world.get_entity_at(&next_position).is_some() {
                             continue;
                         }
                     }
 
                     // Only reproduce if there is an open adjacent square
                     if next_position != self.position {
-                        let child = Box::new(Eater::new(next_position));
+                        let child = Box::new(
+                            Eater::new(next_position)
+                                .with_hunger_threshold(self.get_desire_threshold(Desire::Hunger)),
+                        );
                         offspring = Some(child);
                         new_eater.last_reproduced = 0;
                     }
                 }
             }
-            (Box::new(new_eater), offspring, removed_entity_index)
+            Intent {
+                next: Some(Box::new(new_eater)),
+                fallback: Some(waited),
+                spawn: offspring,
+                eat: eaten_entity_index,
+                died_of: None,
+            }
         }
 
         fn get_color(&self) -> &str {
@@ -619,6 +1088,59 @@ mod eater {
         fn get_position(&self) -> &Position {
             &self.position
         }
+
+        fn to_spec(&self) -> scenario::EntitySpec {
+            scenario::EntitySpec::Eater {
+                position: self.position,
+                hunger: self.get_desire(Desire::Hunger),
+                hunger_threshold: self.get_desire_threshold(Desire::Hunger),
+                age: self.age,
+                last_reproduced: self.last_reproduced,
+            }
+        }
+
+        fn desires(&self) -> Vec<(&'static str, i8)> {
+            self.desires
+                .iter()
+                .map(|(desire, level)| (desire.name(), *level))
+                .collect()
+        }
+
+        fn details(&self, world: &World) -> EntityDetails {
+            let desires = self
+                .desires
+                .keys()
+                .map(|desire| {
+                    let level = DesireLevel {
+                        level: self.get_desire(*desire),
+                        threshold: self.get_desire_threshold(*desire),
+                    };
+                    (String::from(desire.name()), level)
+                })
+                .collect();
+            let goal = self.select_goal(world);
+            let (path, target) = match goal {
+                EaterGoal::GetFood(food_idx) => {
+                    let food_position = world.entities[food_idx].get_position();
+                    let path =
+                        garden_pathfinding::a_star_path(&self.position, food_position, &[], world)
+                            .map(|(path, _)| path.into_iter().skip(1).collect())
+                            .unwrap_or_default();
+                    let target = Target {
+                        id: world.ids[food_idx],
+                        position: *food_position,
+                    };
+                    (path, Some(target))
+                }
+                _ => (vec![], None),
+            };
+            EntityDetails {
+                desires,
+                goal: Some(String::from(goal.name())),
+                path,
+                target,
+            }
+        }
     }
 
     impl Eater {
@@ -627,17 +1149,31 @@ mod eater {
             desires.insert(Desire::Hunger, 0);
 
             let mut desire_threshold = HashMap::new();
-            desire_threshold.insert(Desire::Hunger, 20);
+            desire_threshold.insert(Desire::Hunger, DEFAULT_HUNGER_THRESHOLD);
 
             Eater {
-                position: position,
-                desires: desires,
-                desire_threshold: desire_threshold,
+                position,
+                desires,
+                desire_threshold,
                 age: 0,
                 last_reproduced: 0,
             }
         }
 
+        pub fn restore(position: Position, hunger: i8, age: i32, last_reproduced: i32) -> Eater {
+            let mut eater = Eater::new(position);
+            eater.set_desire(Desire::Hunger, hunger);
+            eater.age = age;
+            eater.last_reproduced = last_reproduced;
+            eater
+        }
+
+        // Offspring take after their parent
+        pub fn with_hunger_threshold(mut self, threshold: i8) -> Eater {
+            self.desire_threshold.insert(Desire::Hunger, threshold);
+            self
+        }
+
         fn set_desire(&mut self, desire: Desire, level: i8) {
             self.desires.insert(desire, level);
         }
@@ -678,11 +1214,13 @@ mod eater {
             let cur_hunger = self.get_desire(Desire::Hunger);
             let hunger_threshold = self.get_desire_threshold(Desire::Hunger);
 
-            if cur_hunger > 99 || self.age > 1000 {
-                goal = EaterGoal::Die
+            if cur_hunger > 99 {
+                goal = EaterGoal::Die(DeathCause::Starvation)
+            } else if self.age > 1000 {
+                goal = EaterGoal::Die(DeathCause::OldAge)
             } else if cur_hunger < 20 && self.age > 40 && self.last_reproduced > 40 {
                 goal = EaterGoal::Reproduce
-            } else if cur_hunger < hunger_threshold || entity_indices.len() == 0 {
+            } else if cur_hunger < hunger_threshold || entity_indices.is_empty() {
                 goal = EaterGoal::Wander
             } else {
                 let mut closest_idx = 0;
@@ -701,7 +1239,7 @@ mod eater {
             goal
         }
 
-        fn get_line_of_sight_entities<'a>(&self, world: &'a World) -> Vec<usize> {
+        fn get_line_of_sight_entities(&self, world: &World) -> Vec<usize> {
             // Omniscient
             world.get_food_entities()
         }
@@ -709,7 +1247,7 @@ mod eater {
         fn pathfind(
             &self,
             goal: &Position,
-            ignored_positions: &Vec<Position>,
+            ignored_positions: &[Position],
             world: &World,
         ) -> (i32, Position) {
             garden_pathfinding::a_star_pathfind(&self.position, goal, ignored_positions, world)
@@ -738,3 +1276,241 @@ mod eater {
         assert_eq!(eater::EaterGoal::GetFood(0), goal);
     }
 }
+
+#[cfg(test)]
+fn crowded_world() -> World {
+    let mut world = World::new(40, 40);
+    world.add_entity(Box::new(food_spawner::FoodSpawner::new(0, 2)));
+    world.add_entity(Box::new(eater_spawner::EaterSpawner::new(0)));
+    for i in 0..200 {
+        let position = Position {
+            x: (i * 7) % 40,
+            y: (i * 13) % 40,
+        };
+        if i % 3 == 0 {
+            world.add_entity(Box::new(eater::Eater::restore(position, 30, 41, 41)));
+        } else {
+            world.add_entity(Box::new(food::Food::new(position)));
+        }
+    }
+    world
+}
+
+#[test]
+fn test_parallel_update_is_deterministic() {
+    use rand_core::SeedableRng;
+
+    let mut sequential = crowded_world();
+    let mut randomizer = rand_pcg::Pcg32::seed_from_u64(3);
+    for _ in 0..30 {
+        sequential.update(&mut randomizer);
+    }
+
+    for threads in [1, 4] {
+        let pool = ThreadPool::new(threads);
+        let mut parallel = crowded_world();
+        let mut randomizer = rand_pcg::Pcg32::seed_from_u64(3);
+        for _ in 0..30 {
+            assert!(parallel.update_parallel(&mut randomizer, &pool));
+        }
+        assert_eq!(parallel.snapshot(), sequential.snapshot());
+    }
+}
+
+#[test]
+fn test_failed_planning_skips_the_tick() {
+    use rand_core::SeedableRng;
+
+    #[derive(Clone)]
+    struct Broken;
+
+    impl Updateable for Broken {
+        fn update(&self, _world: &World, _rng: &mut rand_pcg::Pcg32) -> Intent {
+            panic!("Broken entity")
+        }
+
+        fn to_spec(&self) -> scenario::EntitySpec {
+            scenario::EntitySpec::Food {
+                position: Position { x: 0, y: 0 },
+            }
+        }
+    }
+
+    let pool = ThreadPool::new(4);
+    let mut world = crowded_world();
+    world.add_entity(Box::new(Broken));
+    let before = world.snapshot();
+    let mut randomizer = rand_pcg::Pcg32::seed_from_u64(3);
+    world.unpause();
+    assert!(!world.update_if_active(&mut randomizer, Some(&pool)));
+    assert_eq!(world.current_tick(), 0);
+    assert_eq!(world.snapshot(), before);
+    assert_eq!(randomizer, rand_pcg::Pcg32::seed_from_u64(3));
+}
+
+#[test]
+fn test_food_is_only_eaten_once() {
+    use rand_core::SeedableRng;
+
+    // Both eaters are next to the food and hungry enough to go for it
+    let mut world = World::new(5, 5);
+    world.add_entity(Box::new(food::Food::new(Position { x: 2, y: 2 })));
+    for x in [1, 3] {
+        let position = Position { x, y: 2 };
+        world.add_entity(Box::new(eater::Eater::restore(position, 50, 0, 0)));
+    }
+    world.update(&mut rand_pcg::Pcg32::seed_from_u64(1));
+
+    let hungers: Vec<i8> = world
+        .snapshot()
+        .entities
+        .iter()
+        .filter_map(|entity| match entity {
+            scenario::EntitySpec::Eater { hunger, .. } => Some(*hunger),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(world.get_food_entities().len(), 0);
+    // The first eater ate, the second waited
+    assert_eq!(hungers, vec![31, 51]);
+}
+
+#[test]
+fn test_population_counts_meals_and_deaths() {
+    use rand_core::SeedableRng;
+
+    let mut world = World::new(5, 5);
+    world.add_entity(Box::new(food::Food::new(Position { x: 2, y: 2 })));
+    let eater =
+        |x, y, hunger, age| Box::new(eater::Eater::restore(Position { x, y }, hunger, age, 0));
+    // One about to eat, one starving and one dying of old age
+    world.add_entity(eater(1, 2, 50, 0));
+    world.add_entity(eater(4, 4, 100, 0));
+    world.add_entity(eater(0, 0, 0, 1001));
+    let before = world.population();
+    assert_eq!(before.count("eater"), 3);
+    assert_eq!(before.mean_desires["hunger"], 50.0);
+
+    world.update(&mut rand_pcg::Pcg32::seed_from_u64(1));
+    let after = world.population();
+    assert_eq!(after.tick, 1);
+    assert_eq!(after.count("eater"), 1);
+    assert_eq!(after.count("food"), 0);
+    assert_eq!(after.changes.food_eaten, 1);
+    assert_eq!(after.changes.deaths[&DeathCause::Starvation], 1);
+    assert_eq!(after.changes.deaths[&DeathCause::OldAge], 1);
+    assert_eq!(
+        *world.events(),
+        vec![
+            WorldEvent::Ate { eater: 1, food: 0 },
+            WorldEvent::Died {
+                id: 2,
+                cause: DeathCause::Starvation
+            },
+            WorldEvent::Died {
+                id: 3,
+                cause: DeathCause::OldAge
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_reproduction_events() {
+    use rand_core::SeedableRng;
+
+    let mut world = World::new(3, 1);
+    let parent = world.add_entity(Box::new(eater::Eater::restore(
+        Position { x: 1, y: 0 },
+        0,
+        50,
+        50,
+    )));
+    world.update(&mut rand_pcg::Pcg32::seed_from_u64(1));
+
+    let events = world.events();
+    let child = match events[0] {
+        WorldEvent::Spawned { id, ref kind, .. } if kind == "eater" => id,
+        ref other => panic!("Expected a spawn, got {:?}", other),
+    };
+    assert_eq!(events[1], WorldEvent::Reproduced { parent, child });
+    assert_eq!(world.population().changes.births["eater"], 1);
+}
+
+#[test]
+fn test_eater_details_show_its_target() {
+    let mut world = World::new(5, 5);
+    let food_id = world.add_entity(Box::new(food::Food::new(Position { x: 4, y: 0 })));
+    let eater = eater::Eater::restore(Position { x: 0, y: 0 }, 50, 0, 0);
+    let eater_id = world.add_entity(Box::new(eater));
+
+    let details = world.entity_state(eater_id).unwrap().details;
+    assert_eq!(details.goal.as_deref(), Some("get_food"));
+    assert_eq!(
+        details.target,
+        Some(Target {
+            id: food_id,
+            position: Position { x: 4, y: 0 }
+        })
+    );
+    assert_eq!(details.path.first(), Some(&Position { x: 1, y: 0 }));
+}
+
+#[test]
+fn test_entity_ids_are_stable() {
+    use rand_core::SeedableRng;
+    let mut world = World::default();
+    let mut randomizer = rand_pcg::Pcg32::seed_from_u64(3);
+    let eater_id = world.entity_specs()[2].0;
+    for _ in 0..30 {
+        world.update(&mut randomizer);
+    }
+
+    let specs = world.entity_specs();
+    assert!(specs
+        .iter()
+        .any(|(id, spec)| *id == eater_id && spec.kind() == "eater"));
+    // Food spawned along the way got fresh ids
+    let mut ids: Vec<EntityId> = specs.iter().map(|(id, _)| *id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), specs.len());
+    assert!(ids.iter().all(|id| *id < world.next_id));
+
+    world.remove_entity(eater_id).unwrap();
+    assert!(world.entity_state(eater_id).is_none());
+    assert_eq!(
+        world.remove_entity(eater_id),
+        Err(EntityError::NotFound(eater_id))
+    );
+}
+
+#[test]
+fn test_spawn_and_replace_entities() {
+    let mut world = World::new(5, 5);
+    let food = scenario::EntitySpec::Food {
+        position: Position { x: 1, y: 1 },
+    };
+    let id = world.spawn_entity(&food).unwrap();
+    assert_eq!(
+        world.spawn_entity(&food),
+        Err(EntityError::Occupied(Position { x: 1, y: 1 }))
+    );
+
+    let moved = scenario::EntitySpec::Food {
+        position: Position { x: 2, y: 1 },
+    };
+    world.replace_entity(id, &moved).unwrap();
+    assert_eq!(world.entity_state(id).unwrap().spec, moved);
+    let eater = scenario::EntitySpec::Eater {
+        position: Position { x: 2, y: 1 },
+        hunger: 0,
+        hunger_threshold: DEFAULT_HUNGER_THRESHOLD,
+        age: 0,
+        last_reproduced: 0,
+    };
+    assert!(matches!(
+        world.replace_entity(id, &eater),
+        Err(EntityError::Invalid(_))
+    ));
+}
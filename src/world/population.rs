@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::events::WorldEvent;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeathCause {
+    Starvation,
+    OldAge,
+    // Eaten by another entity. Food being eaten is counted as a meal instead.
+    Predation,
+}
+
+// What happened during a tick, counted as the tick is resolved
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PopulationChanges {
+    // By kind, entities spawned by other entities
+    pub births: BTreeMap<String, usize>,
+    pub deaths: BTreeMap<DeathCause, usize>,
+    pub food_eaten: usize,
+}
+
+// The population as of the end of a tick
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PopulationStats {
+    pub tick: u64,
+    // By kind, spawners included
+    pub counts: BTreeMap<String, usize>,
+    #[serde(flatten)]
+    pub changes: PopulationChanges,
+    // By desire, over the entities that have it
+    pub mean_desires: BTreeMap<String, f64>,
+}
+
+impl PopulationChanges {
+    pub fn from_events(events: &[WorldEvent]) -> PopulationChanges {
+        let mut changes = PopulationChanges::default();
+        for event in events {
+            match event {
+                WorldEvent::Spawned { kind, .. } => {
+                    *changes.births.entry(kind.clone()).or_insert(0) += 1
+                }
+                WorldEvent::Died { cause, .. } => *changes.deaths.entry(*cause).or_insert(0) += 1,
+                WorldEvent::Ate { .. } => changes.food_eaten += 1,
+                WorldEvent::Reproduced { .. } | WorldEvent::Moved { .. } => (),
+            }
+        }
+        changes
+    }
+}
+
+impl PopulationStats {
+    pub fn count(&self, kind: &str) -> usize {
+        self.counts.get(kind).copied().unwrap_or(0)
+    }
+}
+
+// The most recent ticks' stats, oldest first. Only the last `capacity` ticks
+// are kept.
+#[derive(Debug, Clone)]
+pub struct PopulationSeries {
+    capacity: usize,
+    entries: VecDeque<PopulationStats>,
+}
+
+impl PopulationSeries {
+    pub fn new(capacity: usize) -> PopulationSeries {
+        PopulationSeries {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(&mut self, stats: PopulationStats) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(stats);
+    }
+
+    pub fn latest(&self) -> Option<&PopulationStats> {
+        self.entries.back()
+    }
+
+    // Stats for the ticks after `tick`, or all of them
+    pub fn since(&self, tick: Option<u64>) -> Vec<PopulationStats> {
+        self.entries
+            .iter()
+            .filter(|stats| tick.is_none_or(|tick| stats.tick > tick))
+            .cloned()
+            .collect()
+    }
+
+    // Forgets ticks that were rewound
+    pub fn truncate_after(&mut self, tick: u64) {
+        while self.entries.back().is_some_and(|stats| stats.tick > tick) {
+            self.entries.pop_back();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series_is_bounded() {
+        let mut series = PopulationSeries::new(3);
+        for tick in 0..5 {
+            series.record(PopulationStats {
+                tick,
+                ..PopulationStats::default()
+            });
+        }
+        let ticks = |stats: Vec<PopulationStats>| stats.iter().map(|s| s.tick).collect::<Vec<_>>();
+        assert_eq!(ticks(series.since(None)), vec![2, 3, 4]);
+        assert_eq!(ticks(series.since(Some(3))), vec![4]);
+
+        series.truncate_after(2);
+        assert_eq!(series.latest().map(|s| s.tick), Some(2));
+    }
+}
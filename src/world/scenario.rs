@@ -0,0 +1,318 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::*;
+#[cfg(test)]
+use rand_core::SeedableRng;
+
+// A scenario describes the starting state of a world. They are stored as JSON:
+//
+// {"width": 30, "height": 30, "entities": [{"kind": "eater", "position": {"x": 1, "y": 2}}]}
+//
+// Snapshots of a running world use the same format with every field filled
+// in, so a snapshot can be loaded back as a scenario.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub width: i32,
+    pub height: i32,
+    // Ticks the world had run when the snapshot was taken
+    #[serde(default)]
+    pub tick: u64,
+    pub entities: Vec<EntitySpec>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EntitySpec {
+    Food {
+        position: Position,
+    },
+    Eater {
+        position: Position,
+        #[serde(default)]
+        hunger: i8,
+        #[serde(default = "default_hunger_threshold")]
+        hunger_threshold: i8,
+        #[serde(default)]
+        age: i32,
+        #[serde(default)]
+        last_reproduced: i32,
+    },
+    FoodSpawner {
+        spawn_every_x_ticks: i32,
+        #[serde(default)]
+        last_spawned: i32,
+    },
+    EaterSpawner {
+        #[serde(default)]
+        ticks_without_eater: i32,
+        #[serde(default = "default_hunger_threshold")]
+        eater_hunger_threshold: i8,
+    },
+}
+
+fn default_hunger_threshold() -> i8 {
+    DEFAULT_HUNGER_THRESHOLD
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "Unable to read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "Unable to parse scenario: {}", e),
+            ScenarioError::Invalid(reason) => write!(f, "Invalid scenario: {}", reason),
+        }
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Scenario {
+        Scenario {
+            width: 30,
+            height: 30,
+            tick: 0,
+            entities: vec![
+                EntitySpec::FoodSpawner {
+                    spawn_every_x_ticks: 10,
+                    last_spawned: 0,
+                },
+                EntitySpec::EaterSpawner {
+                    ticks_without_eater: 0,
+                    eater_hunger_threshold: DEFAULT_HUNGER_THRESHOLD,
+                },
+                EntitySpec::Eater {
+                    position: Position { x: 15, y: 15 },
+                    hunger: 0,
+                    hunger_threshold: DEFAULT_HUNGER_THRESHOLD,
+                    age: 0,
+                    last_reproduced: 0,
+                },
+                EntitySpec::Food {
+                    position: Position { x: 20, y: 20 },
+                },
+            ],
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+        let contents = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        Scenario::from_json(&contents)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario = serde_json::from_str(contents).map_err(ScenarioError::Parse)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if self.width <= 0 || self.height <= 0 {
+            return Err(ScenarioError::Invalid(format!(
+                "world must be at least 1x1 (got {}x{})",
+                self.width, self.height
+            )));
+        }
+        for entity in self.entities.iter() {
+            entity.validate(self.width, self.height)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ScenarioError> {
+        let contents = serde_json::to_string_pretty(self).map_err(ScenarioError::Parse)?;
+        fs::write(path, contents).map_err(ScenarioError::Io)
+    }
+
+    pub fn build_world(&self) -> World {
+        let mut world = World::new(self.width, self.height);
+        world.tick = self.tick;
+        for entity in self.entities.iter() {
+            world.add_entity(entity.build());
+        }
+        world
+    }
+}
+
+impl EntitySpec {
+    // The `kind` it's tagged with in JSON
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EntitySpec::Food { .. } => "food",
+            EntitySpec::Eater { .. } => "eater",
+            EntitySpec::FoodSpawner { .. } => "food_spawner",
+            EntitySpec::EaterSpawner { .. } => "eater_spawner",
+        }
+    }
+
+    // Spawners act on the whole world and have no cell of their own
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            EntitySpec::Food { position } | EntitySpec::Eater { position, .. } => Some(*position),
+            EntitySpec::FoodSpawner { .. } | EntitySpec::EaterSpawner { .. } => None,
+        }
+    }
+
+    pub fn validate(&self, width: i32, height: i32) -> Result<(), ScenarioError> {
+        if let Some(position) = self.position() {
+            if position.x < 0 || position.x >= width || position.y < 0 || position.y >= height {
+                return Err(ScenarioError::Invalid(format!(
+                    "{:?} is outside of the world",
+                    self
+                )));
+            }
+        }
+        match self {
+            EntitySpec::FoodSpawner {
+                spawn_every_x_ticks,
+                ..
+            } if *spawn_every_x_ticks <= 0 => Err(ScenarioError::Invalid(String::from(
+                "spawn_every_x_ticks must be positive",
+            ))),
+            EntitySpec::Eater {
+                hunger_threshold: threshold,
+                ..
+            }
+            | EntitySpec::EaterSpawner {
+                eater_hunger_threshold: threshold,
+                ..
+            } if !(0..=100).contains(threshold) => Err(ScenarioError::Invalid(format!(
+                "hunger thresholds must be between 0 and 100 (got {})",
+                threshold
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // The same entity with some of its fields changed. Changes are a JSON
+    // object using the field names of the scenario format.
+    pub fn patched(
+        &self,
+        changes: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<EntitySpec, ScenarioError> {
+        let mut value = serde_json::to_value(self).map_err(ScenarioError::Parse)?;
+        let fields = value
+            .as_object_mut()
+            .expect("entity specs serialize to objects");
+        for (field, new_value) in changes {
+            if field == "kind" || !fields.contains_key(field) {
+                return Err(ScenarioError::Invalid(format!(
+                    "{} has no field {:?} that can be changed",
+                    self.kind(),
+                    field
+                )));
+            }
+            fields.insert(field.clone(), new_value.clone());
+        }
+        serde_json::from_value(value).map_err(ScenarioError::Parse)
+    }
+
+    pub fn build(&self) -> EntityType {
+        match self {
+            EntitySpec::Food { position } => Box::new(food::Food::new(*position)),
+            EntitySpec::Eater {
+                position,
+                hunger,
+                hunger_threshold,
+                age,
+                last_reproduced,
+            } => Box::new(
+                eater::Eater::restore(*position, *hunger, *age, *last_reproduced)
+                    .with_hunger_threshold(*hunger_threshold),
+            ),
+            EntitySpec::FoodSpawner {
+                spawn_every_x_ticks,
+                last_spawned,
+            } => Box::new(food_spawner::FoodSpawner::new(
+                *last_spawned,
+                *spawn_every_x_ticks,
+            )),
+            EntitySpec::EaterSpawner {
+                ticks_without_eater,
+                eater_hunger_threshold,
+            } => Box::new(
+                eater_spawner::EaterSpawner::new(*ticks_without_eater)
+                    .with_eater_hunger_threshold(*eater_hunger_threshold),
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_load_default_scenario() {
+    let scenario = Scenario::load(Path::new("scenarios/default.json")).unwrap();
+    assert_eq!(scenario, Scenario::default());
+
+    let world = scenario.build_world();
+    assert_eq!(world.entities.len(), 4);
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let mut world = World::default();
+    let mut randomizer = rand_pcg::Pcg32::seed_from_u64(7);
+    for _ in 0..50 {
+        world.update(&mut randomizer);
+    }
+
+    let snapshot = world.snapshot();
+    let restored = snapshot.build_world();
+
+    assert_eq!(restored.snapshot(), snapshot);
+}
+
+#[test]
+fn test_reject_out_of_bounds_entity() {
+    let scenario = Scenario {
+        width: 5,
+        height: 5,
+        tick: 0,
+        entities: vec![EntitySpec::Food {
+            position: Position { x: 5, y: 0 },
+        }],
+    };
+    assert!(matches!(
+        scenario.validate(),
+        Err(ScenarioError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_patch_entity_spec() {
+    let eater = EntitySpec::Eater {
+        position: Position { x: 1, y: 1 },
+        hunger: 10,
+        hunger_threshold: 20,
+        age: 5,
+        last_reproduced: 0,
+    };
+    let changes = serde_json::json!({ "hunger": 90, "age": 100 });
+    let patched = eater.patched(changes.as_object().unwrap()).unwrap();
+    assert_eq!(
+        patched,
+        EntitySpec::Eater {
+            position: Position { x: 1, y: 1 },
+            hunger: 90,
+            hunger_threshold: 20,
+            age: 100,
+            last_reproduced: 0,
+        }
+    );
+
+    for changes in [
+        serde_json::json!({ "kind": "food" }),
+        serde_json::json!({ "speed": 2 }),
+    ] {
+        assert!(eater.patched(changes.as_object().unwrap()).is_err());
+    }
+}